@@ -0,0 +1,173 @@
+use std::{collections::VecDeque, fs::File, process::ExitCode};
+
+use glob1rs::legacy::{
+    grid::{Coord, Grid, Grid2D},
+    stored_map::{self, LoadOptions, StoredMap},
+    terrain::{TerrainMap, TerrainType},
+};
+
+const TERRAIN_TYPES: [TerrainType; 4] = [
+    TerrainType::Water,
+    TerrainType::Sand,
+    TerrainType::Grass,
+    TerrainType::Resource,
+];
+
+const COMPRESSION_TYPES: [(u8, &str); 5] = [
+    (1, "uncompressed"),
+    (2, "constant"),
+    (3, "linear A"),
+    (4, "linear B"),
+    (5, "multi-tile constant"),
+];
+
+fn terrain_type_index(ty: TerrainType) -> usize {
+    TERRAIN_TYPES.iter().position(|&t| t == ty).unwrap()
+}
+
+/// Count the tiles of each terrain type within rect, clipped to the map
+fn count_terrain(terrain: &TerrainMap, top_left: Coord, size: Coord) -> [usize; 4] {
+    let mut counts = [0; 4];
+    for y in top_left.y..top_left.y + size.y {
+        for x in top_left.x..top_left.x + size.x {
+            let position = Coord::new(x, y);
+            if terrain.is_in_bounds(position) {
+                counts[terrain_type_index(terrain.get(position))] += 1;
+            }
+        }
+    }
+    counts
+}
+
+fn format_counts(counts: &[usize; 4]) -> String {
+    TERRAIN_TYPES
+        .iter()
+        .zip(counts)
+        .map(|(ty, count)| format!("{ty:?}: {count}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Label the passable area reachable from start, returns the number of tiles reached
+fn flood_fill(terrain: &TerrainMap, labels: &mut Grid<u16>, start: Coord, label: u16) -> usize {
+    let mut queue = VecDeque::from([start]);
+    labels.set(start, label);
+    let mut count = 0;
+    while let Some(position) = queue.pop_front() {
+        count += 1;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let next = position + Coord::new(dx, dy);
+                if terrain.is_in_bounds(next) && labels.get(next) == 0 && terrain.passable(next) {
+                    labels.set(next, label);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    count
+}
+
+fn print_info(map: &StoredMap, compression_types: &[u8]) {
+    println!("  {map}");
+
+    // terrain statistics
+    let (width, height) = (map.terrain.width(), map.terrain.height());
+    let size = Coord::new(width as i16, height as i16);
+    let counts = count_terrain(&map.terrain, Coord::new(0, 0), size);
+    println!("  terrain:");
+    for (ty, count) in TERRAIN_TYPES.iter().zip(counts) {
+        let percent = count as f32 * 100.0 / (width * height) as f32;
+        println!(
+            "    {:<10} {count:>8} tiles ({percent:.1}%)",
+            format!("{ty:?}")
+        );
+    }
+
+    // compression statistics
+    println!("  blocks:");
+    for (compression_type, name) in COMPRESSION_TYPES {
+        let count = compression_types
+            .iter()
+            .filter(|&&ty| ty == compression_type)
+            .count();
+        println!("    {compression_type} {name:<20} {count:>5}");
+    }
+
+    // queens and their surroundings
+    println!("  queens:");
+    for (i, &position) in map.queen_positions.iter().enumerate() {
+        if !map.terrain.is_in_bounds(position) {
+            println!("    {i}: {position:?} is outside the map");
+            continue;
+        }
+        let surroundings =
+            count_terrain(&map.terrain, position - Coord::new(2, 2), Coord::new(5, 5));
+        println!(
+            "    {i}: {position:?} on {:?}, surrounded by {}",
+            map.terrain.get(position),
+            format_counts(&surroundings)
+        );
+    }
+
+    // connectivity between queens
+    println!("  connectivity:");
+    let mut labels = Grid::new(width, height, 0);
+    let mut next_label = 1;
+    let mut areas: Vec<(u16, usize, Vec<usize>)> = Vec::new();
+    for (i, &position) in map.queen_positions.iter().enumerate() {
+        if !map.terrain.is_in_bounds(position) || !map.terrain.passable(position) {
+            println!("    queen {i} is not on passable terrain");
+            continue;
+        }
+        let label = labels.get(position);
+        if label == 0 {
+            let size = flood_fill(&map.terrain, &mut labels, position, next_label);
+            areas.push((next_label, size, vec![i]));
+            next_label += 1;
+        } else {
+            let area = areas.iter_mut().find(|area| area.0 == label).unwrap();
+            area.2.push(i);
+        }
+    }
+    for (_, size, queens) in &areas {
+        println!("    queens {queens:?} share an area of {size} passable tiles");
+    }
+    if areas.len() > 1 {
+        println!("    {} disconnected areas", areas.len());
+    }
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let file_names: Vec<_> = std::env::args().skip(1).collect();
+    if file_names.is_empty() {
+        eprintln!("Usage: g1m-info MAP_FILE...");
+        return ExitCode::FAILURE;
+    }
+    let mut success = true;
+    for file_name in file_names {
+        println!("{file_name}:");
+        let result = File::open(&file_name)
+            .map_err(stored_map::MapLoadError::Io)
+            .and_then(|file| {
+                stored_map::load_with_compression_types(
+                    file,
+                    LoadOptions::default(),
+                    &mut rand::thread_rng(),
+                )
+            });
+        match result {
+            Ok((map, compression_types)) => print_info(&map, &compression_types),
+            Err(error) => {
+                println!("  error: {error}");
+                success = false;
+            }
+        }
+    }
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fs::File, io::BufWriter, process::ExitCode};
+
+use glob1rs::legacy::{
+    assets::AssetPaths,
+    grid::{Coord, Rect},
+    map_image, stored_map,
+};
+
+const USAGE: &str = "Usage: g1m-png MAP_FILE PNG_FILE [X Y WIDTH HEIGHT]
+
+Without a region, renders the whole map with one pixel per tile, colored by terrain type.
+With a region, renders its tiles with their sprites, at 32 pixels per tile.
+The sprites and palette are read from GLOB1_SPRITES and GLOB1_PALETTE if set.";
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.len() != 2 && args.len() != 6 {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+    let region = if args.len() == 6 {
+        let values: Result<Vec<i16>, _> = args[2..].iter().map(|arg| arg.parse()).collect();
+        match values.as_deref() {
+            Ok(&[x, y, width, height]) if width > 0 && height > 0 => {
+                Some(Rect::new(Coord::new(x, y), Coord::new(width, height)))
+            }
+            _ => {
+                eprintln!("Invalid region\n\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+    let (map_file_name, png_file_name) = (&args[0], &args[1]);
+    let map = match File::open(map_file_name)
+        .map_err(stored_map::MapLoadError::Io)
+        .and_then(|file| stored_map::load(file, &mut rand::thread_rng()))
+    {
+        Ok(map) => map,
+        Err(error) => {
+            eprintln!("Cannot load {map_file_name}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let image = match region {
+        Some(region) => {
+            let assets = match AssetPaths::from_env().load() {
+                Ok(assets) => assets,
+                Err(error) => {
+                    eprintln!("Cannot load assets: {error}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            map_image::render_region(&map, &assets.sprites, &assets.palette, region)
+        }
+        None => map_image::render_overview(&map),
+    };
+    let result = File::create(png_file_name)
+        .map_err(png::EncodingError::from)
+        .and_then(|file| image.write_png(BufWriter::new(file)));
+    if let Err(error) = result {
+        eprintln!("Cannot write {png_file_name}: {error}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -1,56 +1,111 @@
-use std::fs::File;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+};
 
 use bevy::{
+    app::AppExit,
     ecs::schedule::SystemStage,
     input::{
         mouse::{MouseMotion, MouseWheel},
         Input,
     },
-    math::{IVec3, UVec2, Vec3},
+    math::{Vec2, Vec3},
     prelude::{
-        App, Assets, Camera2d, Camera2dBundle, Commands, CoreStage, EventReader, Handle, Image,
-        KeyCode, MouseButton, Msaa, Query, Res, ResMut, Transform, With,
+        App, Assets, Camera2d, Camera2dBundle, Commands, CoreStage, EventReader, Image, KeyCode,
+        MouseButton, Msaa, Query, Res, ResMut, Transform, With,
     },
     sprite::{TextureAtlas, TextureAtlasBuilder},
-    text::Text,
     time::{FixedTimestep, Time},
     window::Windows,
     DefaultPlugins,
 };
 use bevy_simple_tilemap::prelude::*;
 use glob1rs::legacy::{
-    building::{BuildingSprites, BuildingType},
-    over_map::OverMap,
+    assets::{AssetPaths, GameAssets},
+    building::BuildingType,
+    grid::{Coord, Grid2D},
+    mobility::UnitKind,
+    palette::Palette,
+    render::{
+        sync_sprites, update_terrain_tiles, update_unit_sprites, BuildingSprites, TerrainSprites,
+        UnitSprites,
+    },
+    replay::{Replay, ReplayPlayer},
+    savegame,
+    simulation::{Command, Simulation, SimulationRng},
     sprites, stored_map,
-    unit::{move_units, UnitBundle, UnitSprites},
+    team_color::{self, TEAM_COUNT},
+    unit::TeamId,
 };
-use log::info;
+use rand::SeedableRng;
+
+/// The highest number of simulation steps per tick
+const MAX_SPEED: u32 = 16;
+
+/// The number of ticks to seek by in replays, 30 seconds at normal speed
+const SEEK_TICKS: u64 = 1000;
+
+struct ViewerSettings {
+    title: String,
+    /// The number of teams to build sprites for
+    team_count: usize,
+}
 
-struct MapFileName(String);
+/// Where the commands applied to the simulation come from
+enum Session {
+    /// Commands come from the player, and are recorded if a replay file is given
+    Live {
+        queued: Vec<Command>,
+        recording: Option<(String, Replay)>,
+    },
+    /// Commands come from a replay
+    Playback(Box<ReplayPlayer>),
+}
+
+/// Where to save the game, if anywhere
+struct SaveFileName(Option<String>);
+
+/// The pace of the simulation
+struct Clock {
+    paused: bool,
+    /// Number of simulation steps per tick of the `GLOB1TICK` stage
+    speed: u32,
+}
 
 fn setup(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut over_map: ResMut<OverMap>,
     mut windows: ResMut<Windows>,
-    map_file_name: Res<MapFileName>,
+    settings: Res<ViewerSettings>,
+    mut simulation: ResMut<Simulation>,
+    assets: Res<GameAssets>,
 ) {
-    // Load all images and provide support to create atlases
-    let glob1images = sprites::load();
-    // Helper closure to assemble ranges of images into an atlas
-    let mut build_atlas = |skip_and_takes: Vec<(usize, usize)>| {
-        let mut atlas_builder = TextureAtlasBuilder::default();
+    let team_palettes = team_color::team_palettes(&assets.palette, settings.team_count);
+
+    // Helper closure to assemble ranges of sprites into an atlas, once per palette, also returning their anchors
+    let mut build_atlas = |skip_and_takes: Vec<(usize, usize)>, palettes: &[Palette]| {
+        // Sprites of all teams might not fit in the default size
+        let mut atlas_builder = TextureAtlasBuilder::default().max_size(Vec2::splat(8192.));
         // We have to collect because we need to finish the atlas before the next pass
         let mut handles = Vec::new();
-        for (skip, take) in skip_and_takes {
-            handles.extend(glob1images.iter().skip(skip).take(take).map(|image| {
-                let handle = images.add(image.clone());
-                let image = images.get(&handle).unwrap();
-                let size = image.size().as_uvec2();
-                atlas_builder.add_texture(handle.clone(), image);
-                (handle, size)
-            }));
+        let mut anchors = Vec::new();
+        for palette in palettes {
+            anchors.clear();
+            for &(skip, take) in &skip_and_takes {
+                let range = assets.sprites.iter().enumerate().skip(skip).take(take);
+                handles.extend(range.map(|(n, sprite)| {
+                    let transparent = sprites::has_transparency(n);
+                    let image = sprites::to_image(sprite, palette, transparent);
+                    let handle = images.add(image);
+                    let image = images.get(&handle).unwrap();
+                    let size = image.size().as_uvec2();
+                    atlas_builder.add_texture(handle.clone(), image);
+                    anchors.push(sprite.anchor());
+                    (handle, size)
+                }));
+            }
         }
         let atlas = atlas_builder.finish(&mut images).unwrap();
         let handles_and_index = handles
@@ -61,51 +116,41 @@ fn setup(
             })
             .collect::<Vec<_>>();
         let atlas_handle = texture_atlases.add(atlas);
-        (atlas_handle, handles_and_index)
+        (atlas_handle, handles_and_index, anchors)
     };
 
     // Build building atlas and handles
-    let (building_atlas_handle, building_sprites) = build_atlas(BuildingType::image_ranges());
+    let (building_atlas_handle, building_sprites, building_anchors) =
+        build_atlas(BuildingType::image_ranges(), &team_palettes);
     commands.insert_resource(BuildingSprites {
         texture_atlas: building_atlas_handle,
         sprites: building_sprites,
+        anchors: building_anchors,
     });
 
     // Build unit atlas and handles
-    let (unit_atlas_handle, unit_sprites) = build_atlas(vec![(0, 192)]);
-    let unit_sprites = UnitSprites {
+    let (unit_atlas_handle, unit_sprites, unit_anchors) =
+        build_atlas(vec![(0, 192)], &team_palettes);
+    commands.insert_resource(UnitSprites {
         texture_atlas: unit_atlas_handle,
         sprites: unit_sprites,
-    };
+        anchors: unit_anchors,
+    });
 
-    // Create a new tilemap for terrain
-    let (terrain_atlas_handle, terrain_handles) = build_atlas(vec![(192, 164)]);
-    let map_file_name = &map_file_name.0;
-    let file = File::open(map_file_name).expect("Cannot open map filename");
-    let stored_map = stored_map::load(file).expect("Error reading map");
-    println!("Loaded map: {stored_map}");
-    let tiles: Vec<_> = stored_map
-        .terrain
-        .0
-        .iter()
-        .enumerate()
-        .flat_map(|(x, col)| {
-            let terrain_handles = &terrain_handles;
-            col.iter().enumerate().map(move |(y, &terrain)| {
-                let sprite_index = terrain_handles[terrain as usize].1 as u32;
-                (
-                    IVec3::new(x as i32, -(y as i32), 0),
-                    Some(Tile {
-                        sprite_index,
-                        ..Default::default()
-                    }),
-                )
-            })
-        })
-        .collect();
+    // Create a new tilemap for terrain, terrain sprites have no offsets nor team colors
+    let (terrain_atlas_handle, terrain_handles, _) =
+        build_atlas(vec![(192, 164)], std::slice::from_ref(&assets.palette));
+    let terrain_sprites = TerrainSprites(
+        terrain_handles
+            .into_iter()
+            .map(|(_, index)| index as u32)
+            .collect(),
+    );
     let mut tilemap = TileMap::default();
-    tilemap.set_tiles(tiles);
-    commands.insert_resource(stored_map.terrain);
+    tilemap.set_tiles(terrain_sprites.tiles(&simulation.terrain));
+    // the tilemap shows the tiles changed while creating the terrain already
+    simulation.terrain.take_changed_tiles();
+    commands.insert_resource(terrain_sprites);
 
     // Show terrain
     let terrain_bundle = TileMapBundle {
@@ -113,22 +158,16 @@ fn setup(
         texture_atlas: terrain_atlas_handle,
         ..Default::default()
     };
+    let (width, height) = (simulation.terrain.width(), simulation.terrain.height());
     let mut camera = Camera2dBundle::default();
-    camera.transform.translation = Vec3::new(512.0 * 32.0, -512.0 * 32.0, 10.0);
+    let center = Vec3::new(width as f32 / 2.0, -(height as f32) / 2.0, 0.0);
+    camera.transform.translation = center * 32.0 + Vec3::Z * 10.0;
     commands.spawn_bundle(camera);
     commands.spawn_bundle(terrain_bundle);
 
-    // Create units from queen positions
-    for position in stored_map.queen_positions {
-        UnitBundle::try_spawn(position, &unit_sprites, &mut over_map, &mut commands).unwrap();
-    }
-
-    // add the resources
-    commands.insert_resource(unit_sprites);
-
     // Setup window title
     let window = windows.primary_mut();
-    window.set_title(format!("{map_file_name} – Glob1 map viewer"));
+    window.set_title(settings.title.clone());
 }
 
 // Inspired by: https://github.com/forbjok/bevy_simple_tilemap/blob/master/examples/simple.rs
@@ -186,8 +225,231 @@ fn input_system(
     }
 }
 
+/// Advance the simulation at the pace of the clock, applying the commands of the session
+fn tick_system(
+    mut simulation: ResMut<Simulation>,
+    mut session: ResMut<Session>,
+    clock: Res<Clock>,
+) {
+    if clock.paused {
+        return;
+    }
+    for _ in 0..clock.speed {
+        match &mut *session {
+            Session::Live { queued, recording } => {
+                for command in queued.drain(..) {
+                    if let Some((_, replay)) = recording {
+                        replay.record(simulation.tick(), command);
+                    }
+                    simulation.apply(&command);
+                }
+                simulation.step();
+            }
+            Session::Playback(player) => player.step(&mut simulation),
+        }
+    }
+}
+
+/// Pause with space, change speed with + and -, and when playing a replay,
+/// seek backward and forward with [ and ], and restart with home
+fn clock_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut clock: ResMut<Clock>,
+    mut simulation: ResMut<Simulation>,
+    mut session: ResMut<Session>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        clock.paused = !clock.paused;
+    }
+    if keyboard_input.any_just_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        clock.speed = (clock.speed * 2).min(MAX_SPEED);
+    }
+    if keyboard_input.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        clock.speed = (clock.speed / 2).max(1);
+    }
+    if let Session::Playback(player) = &mut *session {
+        let tick = simulation.tick();
+        if keyboard_input.just_pressed(KeyCode::Home) {
+            player.seek(&mut simulation, 0);
+        } else if keyboard_input.just_pressed(KeyCode::LBracket) {
+            player.seek(&mut simulation, tick.saturating_sub(SEEK_TICKS));
+        } else if keyboard_input.just_pressed(KeyCode::RBracket) {
+            player.seek(&mut simulation, tick + SEEK_TICKS);
+        }
+    }
+}
+
+/// Spawn a worker of the first team where the left mouse button is clicked, unless playing a replay
+fn command_system(
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_transform_query: Query<&Transform, With<Camera2d>>,
+    mut session: ResMut<Session>,
+) {
+    let queued = match &mut *session {
+        Session::Live { queued, .. } => queued,
+        Session::Playback(_) => return,
+    };
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    for transform in camera_transform_query.iter() {
+        let world = transform.translation.truncate()
+            + (cursor - window_size / 2.) * transform.scale.truncate();
+        let position = Coord::new(
+            (world.x / 32.).round() as i16,
+            (-world.y / 32.).round() as i16,
+        );
+        queued.push(Command::SpawnUnit {
+            position,
+            kind: UnitKind::Worker,
+            team: TeamId(0),
+        });
+    }
+}
+
+/// Write the replay being recorded when the game exits
+fn save_replay_system(mut exit_events: EventReader<AppExit>, session: Res<Session>) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+    if let Session::Live {
+        recording: Some((file_name, replay)),
+        ..
+    } = &*session
+    {
+        match File::create(file_name).and_then(|file| replay.write(BufWriter::new(file))) {
+            Ok(()) => println!("Saved replay to {file_name}"),
+            Err(error) => eprintln!("Cannot save replay to {file_name}: {error}"),
+        }
+    }
+}
+
+/// Save the game when pressing F5 and when the game exits, if a savegame file is given
+fn save_game_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut exit_events: EventReader<AppExit>,
+    simulation: Res<Simulation>,
+    save_file_name: Res<SaveFileName>,
+) {
+    let exiting = exit_events.iter().next().is_some();
+    if !exiting && !keyboard_input.just_pressed(KeyCode::F5) {
+        return;
+    }
+    if let Some(file_name) = &save_file_name.0 {
+        let result = File::create(file_name).and_then(|file| savegame::save(&simulation, file));
+        match result {
+            Ok(()) => println!("Saved game at tick {} to {file_name}", simulation.tick()),
+            Err(error) => eprintln!("Cannot save game to {file_name}: {error}"),
+        }
+    }
+}
+
+const USAGE: &str = "Usage: glob1 [--seed SEED] [--record REPLAY_FILE] [--save SAVE_FILE] MAP_FILE
+       glob1 --load SAVE_FILE [--save SAVE_FILE]
+       glob1 --replay REPLAY_FILE [--save SAVE_FILE]";
+
 fn main() {
-    let file_name = std::env::args().nth(1).expect("Missing map filename");
+    let mut file_name = None;
+    let mut seed = None;
+    let mut record_file_name = None;
+    let mut replay_file_name = None;
+    let mut load_file_name = None;
+    let mut save_file_name = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let value = args.next().expect(USAGE);
+                seed = Some(value.parse().expect("Seed must be an unsigned integer"));
+            }
+            "--record" => record_file_name = Some(args.next().expect(USAGE)),
+            "--replay" => replay_file_name = Some(args.next().expect(USAGE)),
+            "--load" => load_file_name = Some(args.next().expect(USAGE)),
+            "--save" => save_file_name = Some(args.next().expect(USAGE)),
+            _ => file_name = Some(arg),
+        }
+    }
+
+    let (title, simulation, session) = match (replay_file_name, load_file_name) {
+        (Some(_), Some(_)) => panic!("{USAGE}"),
+        (Some(replay_file_name), None) => {
+            let file = File::open(&replay_file_name).expect("Cannot open replay file");
+            let replay = Replay::read(BufReader::new(file)).expect("Error reading replay");
+            println!(
+                "Replaying {} commands with seed {}",
+                replay.commands.len(),
+                replay.seed
+            );
+            let (player, simulation) = ReplayPlayer::new(replay).expect("Error reading map");
+            let title = format!("{replay_file_name} – Glob1 replay");
+            (title, simulation, Session::Playback(Box::new(player)))
+        }
+        (None, Some(load_file_name)) => {
+            assert!(record_file_name.is_none(), "Cannot record a loaded game");
+            let file = File::open(&load_file_name).expect("Cannot open savegame");
+            let simulation = savegame::load(file).expect("Error reading savegame");
+            println!("Loaded game at tick {}", simulation.tick());
+            let title = format!("{load_file_name} – Glob1 map viewer");
+            let session = Session::Live {
+                queued: Vec::new(),
+                recording: None,
+            };
+            (title, simulation, session)
+        }
+        (None, None) => {
+            let file_name = file_name.expect(USAGE);
+            let seed = seed.unwrap_or_else(rand::random);
+            println!("Seed: {seed}");
+            let map = std::fs::read(&file_name).expect("Cannot open map filename");
+            let mut rng = SimulationRng::seed_from_u64(seed);
+            let stored_map = stored_map::load(map.as_slice(), &mut rng).expect("Error reading map");
+            println!("Loaded map: {stored_map}");
+            let simulation = Simulation::from_stored_map(&stored_map, rng);
+            let recording = record_file_name.map(|name| (name, Replay::new(seed, map)));
+            let title = format!("{file_name} – Glob1 map viewer");
+            let session = Session::Live {
+                queued: Vec::new(),
+                recording,
+            };
+            (title, simulation, session)
+        }
+    };
+
+    // Build sprites for the teams of the units and buildings, and the ones given commands in a replay
+    let mut teams: Vec<TeamId> = simulation.units.iter().map(|unit| unit.team).collect();
+    teams.extend(simulation.buildings.iter().map(|building| building.team));
+    if let Session::Playback(player) = &session {
+        teams.extend(
+            player
+                .replay()
+                .commands
+                .iter()
+                .filter_map(|(_, command)| match *command {
+                    Command::SpawnUnit { team, .. } => Some(team),
+                    Command::SetPolicy { .. } => None,
+                }),
+        );
+    }
+    let team_count = teams
+        .into_iter()
+        .map(|team| team.0 as usize + 1)
+        .max()
+        .unwrap_or(1)
+        .min(TEAM_COUNT);
+
+    let assets = AssetPaths::from_env()
+        .load()
+        .unwrap_or_else(|error| panic!("Cannot load assets: {error}"));
     static GLOB1TICK: &str = "glob1tick";
 
     App::new()
@@ -195,15 +457,29 @@ fn main() {
         .insert_resource(Msaa { samples: 1 })
         .add_plugins(DefaultPlugins)
         .add_plugin(SimpleTileMapPlugin)
-        .insert_resource(MapFileName(file_name))
-        .insert_resource(OverMap::default())
+        .insert_resource(ViewerSettings { title, team_count })
+        .insert_resource(simulation)
+        .insert_resource(session)
+        .insert_resource(SaveFileName(save_file_name))
+        .insert_resource(Clock {
+            paused: false,
+            speed: 1,
+        })
+        .insert_resource(assets)
         .add_system(input_system)
+        .add_system(clock_system)
+        .add_system(command_system)
         .add_startup_system(setup)
         .add_stage_before(
             CoreStage::Update,
             GLOB1TICK,
             SystemStage::single_threaded().with_run_criteria(FixedTimestep::step(0.03)),
         )
-        .add_system_to_stage(GLOB1TICK, move_units)
+        .add_system_to_stage(GLOB1TICK, tick_system)
+        .add_system(sync_sprites)
+        .add_system(update_unit_sprites)
+        .add_system(update_terrain_tiles)
+        .add_system_to_stage(CoreStage::Last, save_replay_system)
+        .add_system_to_stage(CoreStage::Last, save_game_system)
         .run();
 }
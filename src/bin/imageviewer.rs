@@ -1,5 +1,8 @@
 use bevy::prelude::*;
-use glob1rs::legacy::sprites;
+use glob1rs::legacy::{
+    assets::{AssetPaths, GameAssets},
+    sprites,
+};
 
 #[derive(Default)]
 struct ViewerState {
@@ -24,11 +27,16 @@ fn setup(
     mut images: ResMut<Assets<Image>>,
     mut state: ResMut<ViewerState>,
     windows: ResMut<Windows>,
+    assets: Res<GameAssets>,
 ) {
-    let glob1images = sprites::load();
-    state.images = glob1images
-        .into_iter()
-        .map(|image| images.add(image))
+    state.images = assets
+        .sprites
+        .iter()
+        .enumerate()
+        .map(|(n, sprite)| {
+            let image = sprites::to_image(sprite, &assets.palette, sprites::has_transparency(n));
+            images.add(image)
+        })
         .collect::<Vec<_>>();
     commands.spawn_bundle(Camera2dBundle::default());
     commands.spawn_bundle(SpriteBundle {
@@ -71,8 +79,12 @@ fn keyboard_input(
 }
 
 fn main() {
+    let assets = AssetPaths::from_env()
+        .load()
+        .unwrap_or_else(|error| panic!("Cannot load assets: {error}"));
     App::new()
         .insert_resource(ViewerState::default())
+        .insert_resource(assets)
         .add_plugins(DefaultPlugins)
         .add_startup_system(setup)
         .add_system(keyboard_input)
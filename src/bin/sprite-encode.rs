@@ -0,0 +1,98 @@
+use std::{fs, fs::File, io::BufReader, path::Path, process::ExitCode};
+
+use glob1rs::legacy::{
+    assets::AssetPaths,
+    map_image::RgbaImage,
+    sprite_manifest::SpriteManifest,
+    sprites::{self, Sprite},
+};
+use log::warn;
+
+const USAGE: &str = "Usage: sprite-encode MANIFEST_FILE SPRITE_FILE
+
+Builds a sprite bank in SPRITE_FILE from the PNG files listed in MANIFEST_FILE,
+as written by sprite-export. The size of each sprite is the one of its PNG file,
+and its colors are quantized to the palette read from GLOB1_PALETTE if set.";
+
+/// Load the sprites listed in the manifest, quantized to the palette
+fn load_sprites(manifest_file_name: &str) -> Result<Vec<Sprite>, String> {
+    let text = fs::read_to_string(manifest_file_name)
+        .map_err(|error| format!("Cannot read {manifest_file_name}: {error}"))?;
+    let manifest = SpriteManifest::from_toml(&text)
+        .map_err(|error| format!("Cannot parse {manifest_file_name}: {error}"))?;
+    let palette = AssetPaths::from_env()
+        .load_palette()
+        .map_err(|error| format!("Cannot load palette: {error}"))?;
+    let dir = Path::new(manifest_file_name)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let mut sprites = Vec::with_capacity(manifest.sprites.len());
+    for (n, entry) in manifest.sprites.iter().enumerate() {
+        if entry.index != n {
+            return Err(format!(
+                "Sprite {} is listed at position {n}, sprites must be listed in order without gaps",
+                entry.index
+            ));
+        }
+        let path = dir.join(&entry.file);
+        let image = File::open(&path)
+            .map_err(png::DecodingError::from)
+            .and_then(|file| RgbaImage::read_png(BufReader::new(file)))
+            .map_err(|error| format!("Cannot read {}: {error}", path.display()))?;
+        let too_large = |value: u32| value > u16::MAX as u32;
+        if [image.width, image.height, entry.x_extra, entry.y_extra]
+            .into_iter()
+            .any(too_large)
+        {
+            return Err(format!(
+                "Sprite {n} has a size or offset larger than {}",
+                u16::MAX
+            ));
+        }
+        if (image.width, image.height) != (entry.width, entry.height) {
+            warn!(
+                "Sprite {n} is {} x {} but listed as {} x {}, using the size of {}",
+                image.width,
+                image.height,
+                entry.width,
+                entry.height,
+                path.display()
+            );
+        }
+        let transparent = sprites::has_transparency(n);
+        let mut sprite = Sprite::from_rgba(
+            image.width,
+            image.height,
+            &image.data,
+            &palette,
+            transparent,
+        );
+        sprite.x_extra = entry.x_extra;
+        sprite.y_extra = entry.y_extra;
+        sprites.push(sprite);
+    }
+    Ok(sprites)
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+    let (manifest_file_name, sprite_file_name) = (&args[0], &args[1]);
+    let sprites = match load_sprites(manifest_file_name) {
+        Ok(sprites) => sprites,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = fs::write(sprite_file_name, sprites::encode(&sprites)) {
+        eprintln!("Cannot write {sprite_file_name}: {error}");
+        return ExitCode::FAILURE;
+    }
+    println!("Encoded {} sprites to {sprite_file_name}", sprites.len());
+    ExitCode::SUCCESS
+}
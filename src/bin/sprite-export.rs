@@ -0,0 +1,65 @@
+use std::{fs, fs::File, io::BufWriter, path::Path, process::ExitCode};
+
+use glob1rs::legacy::{
+    assets::AssetPaths, map_image::RgbaImage, sprite_manifest::SpriteManifest, sprites,
+};
+
+const USAGE: &str = "Usage: sprite-export OUTPUT_DIR [SPRITE_FILE]
+
+Writes each sprite of the sprite bank to OUTPUT_DIR/NNN.png, NNN being its index,
+and describes them in OUTPUT_DIR/sprites.toml.
+The sprites are read from SPRITE_FILE, or from GLOB1_SPRITES if set,
+and the palette from GLOB1_PALETTE if set.";
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+    let output_dir = Path::new(&args[0]);
+    let mut paths = AssetPaths::from_env();
+    if let Some(sprite_file) = args.get(1) {
+        paths.sprites = Some(sprite_file.into());
+    }
+    let assets = match paths.load() {
+        Ok(assets) => assets,
+        Err(error) => {
+            eprintln!("Cannot load assets: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = fs::create_dir_all(output_dir) {
+        eprintln!("Cannot create {}: {error}", output_dir.display());
+        return ExitCode::FAILURE;
+    }
+    let manifest = SpriteManifest::from_sprites(&assets.sprites);
+    for (entry, sprite) in manifest.sprites.iter().zip(&assets.sprites) {
+        let transparent = sprites::has_transparency(entry.index);
+        let image = RgbaImage::from_sprite(sprite, &assets.palette, transparent);
+        let path = output_dir.join(&entry.file);
+        let result = File::create(&path)
+            .map_err(png::EncodingError::from)
+            .and_then(|file| image.write_png(BufWriter::new(file)));
+        if let Err(error) = result {
+            eprintln!("Cannot write {}: {error}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    let path = output_dir.join("sprites.toml");
+    let result = manifest
+        .to_toml()
+        .map_err(|error| error.to_string())
+        .and_then(|text| fs::write(&path, text).map_err(|error| error.to_string()));
+    if let Err(error) = result {
+        eprintln!("Cannot write {}: {error}", path.display());
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Exported {} sprites to {}",
+        manifest.sprites.len(),
+        output_dir.display()
+    );
+    ExitCode::SUCCESS
+}
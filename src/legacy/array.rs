@@ -1,20 +0,0 @@
-/// A macro similar to `vec![$elem; $size]` which returns a boxed array.
-///
-/// ```rustc
-///     let _: Box<[u8; 1024]> = box_array![0; 1024];
-/// ```
-/// from: https://stackoverflow.com/questions/25805174/creating-a-fixed-size-array-on-heap-in-rust
-macro_rules! box_array {
-    ($val:expr ; $len:expr) => {{
-        // Use a generic function so that the pointer cast remains type-safe
-        fn vec_to_boxed_array<T>(vec: Vec<T>) -> Box<[T; $len]> {
-            let boxed_slice = vec.into_boxed_slice();
-
-            let ptr = ::std::boxed::Box::into_raw(boxed_slice) as *mut [T; $len];
-
-            unsafe { Box::from_raw(ptr) }
-        }
-
-        vec_to_boxed_array(vec![$val; $len])
-    }};
-}
@@ -0,0 +1,151 @@
+use std::{env, path::PathBuf};
+
+use super::{
+    palette::{self, Palette, PaletteLoadError},
+    sprites::{self, Sprite, SpriteLoadError},
+};
+
+/// The sprites and palette of the game
+pub struct GameAssets {
+    pub sprites: Vec<Sprite>,
+    pub palette: Palette,
+}
+
+/// Where to load the assets from, None meaning the ones embedded in the binary
+#[derive(Debug, Clone, Default)]
+pub struct AssetPaths {
+    pub sprites: Option<PathBuf>,
+    pub palette: Option<PathBuf>,
+}
+
+impl AssetPaths {
+    /// Take the paths from the GLOB1_SPRITES and GLOB1_PALETTE environment variables
+    pub fn from_env() -> Self {
+        Self {
+            sprites: env::var_os("GLOB1_SPRITES").map(PathBuf::from),
+            palette: env::var_os("GLOB1_PALETTE").map(PathBuf::from),
+        }
+    }
+
+    /// Load the assets, failing if a path is missing and the embedded assets are not available
+    pub fn load(&self) -> Result<GameAssets, AssetLoadError> {
+        let sprites = match &self.sprites {
+            Some(path) => {
+                sprites::load_from_path(path).map_err(|source| AssetLoadError::Sprites {
+                    path: Some(path.clone()),
+                    source,
+                })?
+            }
+            None => embedded_sprites()?,
+        };
+        let palette = self.load_palette()?;
+        Ok(GameAssets { sprites, palette })
+    }
+
+    /// Load only the palette, failing if its path is missing and the embedded assets are not available
+    pub fn load_palette(&self) -> Result<Palette, AssetLoadError> {
+        match &self.palette {
+            Some(path) => palette::load_from_path(path).map_err(|source| AssetLoadError::Palette {
+                path: path.clone(),
+                source,
+            }),
+            None => embedded_palette(),
+        }
+    }
+}
+
+/// An error while loading the assets, keeping the path of the file and the error of its decoding
+#[derive(Debug)]
+pub enum AssetLoadError {
+    /// The sprite bank could not be loaded, from the embedded one if path is None
+    Sprites {
+        path: Option<PathBuf>,
+        source: SpriteLoadError,
+    },
+    /// The palette could not be loaded
+    Palette {
+        path: PathBuf,
+        source: PaletteLoadError,
+    },
+    /// The assets are not embedded in this build, and the environment variable giving their file is not set
+    NotEmbedded { variable: &'static str },
+}
+impl std::fmt::Display for AssetLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use AssetLoadError::*;
+        match self {
+            Sprites {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {source}", path.display()),
+            Sprites { path: None, source } => write!(f, "embedded sprites: {source}"),
+            Palette { path, source } => write!(f, "{}: {source}", path.display()),
+            NotEmbedded { variable } => write!(
+                f,
+                "assets are not embedded in this build, set {variable} to a file"
+            ),
+        }
+    }
+}
+impl std::error::Error for AssetLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssetLoadError::Sprites { source, .. } => Some(source),
+            AssetLoadError::Palette { source, .. } => Some(source),
+            AssetLoadError::NotEmbedded { .. } => None,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-assets")]
+fn embedded_sprites() -> Result<Vec<Sprite>, AssetLoadError> {
+    sprites::load_indexed().map_err(|source| AssetLoadError::Sprites { path: None, source })
+}
+
+#[cfg(not(feature = "embedded-assets"))]
+fn embedded_sprites() -> Result<Vec<Sprite>, AssetLoadError> {
+    Err(AssetLoadError::NotEmbedded {
+        variable: "GLOB1_SPRITES",
+    })
+}
+
+#[cfg(feature = "embedded-assets")]
+fn embedded_palette() -> Result<Palette, AssetLoadError> {
+    Ok(*palette::PALETTE)
+}
+
+#[cfg(not(feature = "embedded-assets"))]
+fn embedded_palette() -> Result<Palette, AssetLoadError> {
+    Err(AssetLoadError::NotEmbedded {
+        variable: "GLOB1_PALETTE",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_errors() {
+        let paths = AssetPaths {
+            sprites: Some(PathBuf::from("/nonexistent/sprites.dat")),
+            palette: Some(PathBuf::from("/nonexistent/palette.pal")),
+        };
+        assert!(matches!(
+            paths.load(),
+            Err(AssetLoadError::Sprites {
+                path: Some(_),
+                source: SpriteLoadError::Io(_)
+            })
+        ));
+        let error = paths.load_palette().unwrap_err();
+        assert!(matches!(
+            error,
+            AssetLoadError::Palette {
+                source: PaletteLoadError::Io(_),
+                ..
+            }
+        ));
+        assert!(error.to_string().starts_with("/nonexistent/palette.pal: "));
+    }
+}
@@ -1,37 +1,30 @@
-// see: https://github.com/bevyengine/bevy/discussions/5166
-#![allow(clippy::forget_non_drop)]
-
-use bevy::{
-    prelude::{Bundle, Commands, Component, Entity, Handle, Image, Transform, UVec2},
-    sprite::{SpriteSheetBundle, TextureAtlas, TextureAtlasSprite},
-};
 use num_enum::IntoPrimitive;
 
 use super::{
-    grid::{grid_to_world, Coord, Grid2D, Rect},
-    over_map::{OverMap, OverMapTile},
+    grid::{Coord, Rect},
+    sprites::Sprite,
+    unit::TeamId,
 };
 
-#[derive(Default)]
-pub struct BuildingSprites {
-    pub texture_atlas: Handle<TextureAtlas>,
-    pub sprites: Vec<((Handle<Image>, UVec2), usize)>,
-}
+/// The identifier of a building in the simulation, its index in the buildings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BuildingId(pub u32);
 
-#[derive(Component)]
-pub struct BuildingPosition {
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Building {
+    pub ty: BuildingType,
     pub position: Coord,
     pub size: Coord,
+    pub team: TeamId,
 }
-
-#[derive(Bundle)]
-pub struct BuildingBundle {
-    pub position: BuildingPosition,
-    #[bundle]
-    pub sprite: SpriteSheetBundle,
+impl Building {
+    /// The tiles covered by the building
+    pub fn footprint(&self) -> Rect {
+        Rect::new(self.position, self.size)
+    }
 }
 
-#[derive(Clone, Copy, Debug, Default, IntoPrimitive)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, IntoPrimitive)]
 #[repr(u8)]
 pub enum BuildingLevel {
     #[default]
@@ -42,7 +35,7 @@ pub enum BuildingLevel {
     Level4,
 }
 
-#[derive(Clone, Copy, Debug, Default, IntoPrimitive)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, IntoPrimitive)]
 #[repr(u8)]
 pub enum WonderLevel {
     #[default]
@@ -56,7 +49,7 @@ pub enum WonderLevel {
     Level7,
 }
 
-#[derive(Clone, Copy, Debug, Default, IntoPrimitive)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, IntoPrimitive)]
 #[repr(u8)]
 pub enum ConstructionSiteType {
     #[default]
@@ -69,7 +62,7 @@ pub enum ConstructionSiteType {
     Size8,
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum BuildingType {
     #[default]
     Hive,
@@ -93,7 +86,7 @@ impl BuildingType {
             (492, 6),  // various size construction sites
         ]
     }
-    /// The index of the corresponding image in BuildingSprites
+    /// The index of the corresponding image in the images of `image_ranges`
     pub fn image_index(&self) -> usize {
         match *self {
             BuildingType::Hive => 0,
@@ -109,44 +102,21 @@ impl BuildingType {
             BuildingType::ConstructionSite(ty) => 49 + u8::from(ty) as usize,
         }
     }
+    /// The index of the corresponding image in all images
+    pub fn sprite_index(&self) -> usize {
+        let mut index = self.image_index();
+        for (skip, take) in Self::image_ranges() {
+            if index < take {
+                return skip + index;
+            }
+            index -= take;
+        }
+        unreachable!("image index out of the image ranges")
+    }
     // Currently all buildings are square, and controlled by their x-axis length
-    pub fn tile_size(&self, building_sprites: &BuildingSprites) -> i16 {
-        let index = self.image_index();
-        let size = (building_sprites.sprites[index].0).1.to_array()[0];
+    pub fn tile_size(&self, sprites: &[Sprite]) -> i16 {
+        let size = sprites[self.sprite_index()].width;
         assert_eq!(size & 0x01f, 0);
         (size / 32) as i16
     }
 }
-
-impl BuildingBundle {
-    pub fn try_spawn(
-        position: Coord,
-        ty: BuildingType,
-        building_sprites: &BuildingSprites,
-        over_map: &mut OverMap,
-        commands: &mut Commands,
-    ) -> Option<Entity> {
-        let sprite_index = ty.image_index();
-        let side_len = ty.tile_size(building_sprites);
-        let size = Coord::new(side_len, side_len);
-        let rect = Rect::new(position, size);
-        if !over_map.rect_has_value(rect, OverMapTile::Empty) {
-            return None;
-        }
-        let id = commands
-            .spawn()
-            .insert_bundle(BuildingBundle {
-                position: BuildingPosition { position, size },
-                sprite: SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(sprite_index),
-                    texture_atlas: building_sprites.texture_atlas.clone(),
-                    transform: Transform::from_translation(grid_to_world(position)),
-                    ..Default::default()
-                },
-            })
-            .id();
-        over_map.set_rect_value(rect, OverMapTile::Building(id));
-        // TODO: later use team as an entity
-        Some(id)
-    }
-}
@@ -3,6 +3,7 @@ use derive_new::new;
 
 pub type Coord = Vector2<i16>;
 
+#[cfg(feature = "bevy")]
 pub fn grid_to_world(coord: Coord) -> bevy::prelude::Vec3 {
     bevy::prelude::Vec3::new(
         coord.x as f32 * 32.0,
@@ -10,6 +11,7 @@ pub fn grid_to_world(coord: Coord) -> bevy::prelude::Vec3 {
         (coord.y as f32 + 1.0) / 1024.,
     )
 }
+#[cfg(feature = "bevy")]
 pub fn grid_to_world_with_delta(coord: Coord, delta: Coord) -> bevy::prelude::Vec3 {
     bevy::prelude::Vec3::new(
         coord.x as f32 * 32.0 + delta.x as f32,
@@ -18,26 +20,26 @@ pub fn grid_to_world_with_delta(coord: Coord, delta: Coord) -> bevy::prelude::Ve
     )
 }
 
-#[derive(Debug, Clone, Copy, new)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, new)]
 pub struct Rect {
     pub top_left: Coord,
     pub size: Coord,
 }
 
 pub trait Grid2D<T: Copy + PartialEq> {
-    const W: usize;
-    const H: usize;
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
 
-    fn is_in_bounds(position: Coord) -> bool {
+    fn is_in_bounds(&self, position: Coord) -> bool {
         position.x >= 0
-            && position.x < Self::W as i16
+            && (position.x as usize) < self.width()
             && position.y >= 0
-            && position.y < Self::H as i16
+            && (position.y as usize) < self.height()
     }
 
     fn for_each(&mut self, f: impl Fn(T, Coord) -> T) {
-        for y in 0..Self::H as i16 {
-            for x in 0..Self::W as i16 {
+        for y in 0..self.height() as i16 {
+            for x in 0..self.width() as i16 {
                 let position = Coord::new(x, y);
                 self.set(position, f(self.get(position), position));
             }
@@ -70,8 +72,12 @@ pub trait Grid2D<T: Copy + PartialEq> {
 }
 
 impl<T: Copy + PartialEq, const W: usize, const H: usize> Grid2D<T> for [[T; W]; H] {
-    const W: usize = W;
-    const H: usize = H;
+    fn width(&self) -> usize {
+        W
+    }
+    fn height(&self) -> usize {
+        H
+    }
 
     fn get(&self, position: Coord) -> T {
         self[position.y as usize][position.x as usize]
@@ -81,13 +87,81 @@ impl<T: Copy + PartialEq, const W: usize, const H: usize> Grid2D<T> for [[T; W];
     }
 }
 
+/// A grid allocated on the heap, whose size is chosen at runtime, stored row by row
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    data: Vec<T>,
+}
+impl<T: Clone> Grid<T> {
+    pub fn new(width: usize, height: usize, value: T) -> Self {
+        assert!(width <= i16::MAX as usize && height <= i16::MAX as usize);
+        Self {
+            width,
+            height,
+            data: vec![value; width * height],
+        }
+    }
+}
+impl<T> Grid<T> {
+    /// Iterate over all values, row by row
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.data.iter()
+    }
+    /// Iterate mutably over all values, row by row
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.data.iter_mut()
+    }
+    /// The values of a row
+    pub fn row(&self, y: i16) -> &[T] {
+        let start = self.index(Coord::new(0, y));
+        &self.data[start..start + self.width]
+    }
+    /// The values of a row, mutably
+    pub fn row_mut(&mut self, y: i16) -> &mut [T] {
+        let start = self.index(Coord::new(0, y));
+        &mut self.data[start..start + self.width]
+    }
+    fn index(&self, position: Coord) -> usize {
+        // checked also in release, as an x out of bounds would silently alias into the next row
+        assert!(
+            position.x >= 0
+                && (position.x as usize) < self.width
+                && position.y >= 0
+                && (position.y as usize) < self.height,
+            "position {position:?} out of a {}x{} grid",
+            self.width,
+            self.height
+        );
+        position.y as usize * self.width + position.x as usize
+    }
+}
+
+impl<T: Copy + PartialEq> Grid2D<T> for Grid<T> {
+    fn width(&self) -> usize {
+        self.width
+    }
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn get(&self, position: Coord) -> T {
+        self.data[self.index(position)]
+    }
+    fn set(&mut self, position: Coord, value: T) {
+        let index = self.index(position);
+        self.data[index] = value;
+    }
+}
+
 macro_rules! impl_grid2d_delegate {
     ($tile_ty: ty, $map_ty: ty) => {
         impl Grid2D<$tile_ty> for $map_ty {
-            const W: usize = 1024;
-            const H: usize = 1024;
             delegate! {
                 to self.0 {
+                    fn width(&self) -> usize;
+                    fn height(&self) -> usize;
                     fn get(&self, position: Coord) -> $tile_ty;
                     fn set(&mut self, position: Coord, value: $tile_ty);
                 }
@@ -95,3 +169,15 @@ macro_rules! impl_grid2d_delegate {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic]
+    fn set_out_of_row() {
+        let mut grid = Grid::new(4, 4, 0);
+        grid.set(Coord::new(5, 0), 1);
+    }
+}
@@ -0,0 +1,276 @@
+use std::io::{Read, Write};
+
+use log::warn;
+
+use super::{
+    grid::{Coord, Grid2D, Rect},
+    palette::Palette,
+    sprites::Sprite,
+    stored_map::StoredMap,
+    terrain::TerrainType,
+};
+
+/// Width and height of a tile in pixels
+pub const TILE_SIZE: u32 = 32;
+
+const QUEEN_COLOR: [u8; 4] = [255, 0, 0, 255];
+const VIEW_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+/// An image with 8-bit RGBA pixels, stored row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+impl RgbaImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+    /// Convert a sprite using the palette, without its padding column.
+    /// If transparent is true, entry 0 is transparent.
+    pub fn from_sprite(sprite: &Sprite, palette: &Palette, transparent: bool) -> Self {
+        let width = sprite.stored_width();
+        let rgba = sprite.to_rgba(palette, transparent);
+        let data = rgba
+            .chunks_exact(sprite.width as usize * 4)
+            .flat_map(|row| &row[..width as usize * 4])
+            .copied()
+            .collect();
+        Self {
+            width,
+            height: sprite.height,
+            data,
+        }
+    }
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+    /// Set a pixel, ignoring coordinates outside the image
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: [u8; 4]) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.data[i..i + 4].copy_from_slice(&color);
+    }
+    /// Draw the outline of a square of given side length centered on x, y
+    fn draw_square(&mut self, x: i32, y: i32, side: i32, color: [u8; 4]) {
+        let (left, top) = (x - side / 2, y - side / 2);
+        for i in 0..side {
+            self.set_pixel(left + i, top, color);
+            self.set_pixel(left + i, top + side - 1, color);
+            self.set_pixel(left, top + i, color);
+            self.set_pixel(left + side - 1, top + i, color);
+        }
+    }
+    /// Read a PNG file of any color type, converting it to 8-bit RGBA
+    pub fn read_png(input: impl Read) -> Result<Self, png::DecodingError> {
+        let mut decoder = png::Decoder::new(input);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let pixels = &buffer[..info.buffer_size()];
+        let data = match info.color_type {
+            png::ColorType::Rgba => pixels.to_vec(),
+            png::ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            // indexed images are expanded to RGB or RGBA
+            png::ColorType::Grayscale | png::ColorType::Indexed => {
+                pixels.iter().flat_map(|&g| [g, g, g, 255]).collect()
+            }
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+    /// Write the image to a PNG file
+    pub fn write_png(&self, output: impl Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(output, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)
+    }
+}
+
+/// The color of a terrain type in the overview
+pub fn terrain_color(ty: TerrainType) -> [u8; 4] {
+    match ty {
+        TerrainType::Water => [40, 80, 170, 255],
+        TerrainType::Sand => [220, 200, 130, 255],
+        TerrainType::Grass => [70, 150, 60, 255],
+        TerrainType::Resource => [120, 80, 40, 255],
+    }
+}
+
+/// Render the whole map with one pixel per tile, colored by terrain type,
+/// with queen positions marked in red and the view position in white.
+pub fn render_overview(map: &StoredMap) -> RgbaImage {
+    let (width, height) = (map.terrain.width() as u32, map.terrain.height() as u32);
+    let mut image = RgbaImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let ty = map.terrain.get(Coord::new(x as i16, y as i16));
+            image.set_pixel(x as i32, y as i32, terrain_color(ty));
+        }
+    }
+    for position in &map.queen_positions {
+        for side in [1, 3, 5] {
+            image.draw_square(position.x as i32, position.y as i32, side, QUEEN_COLOR);
+        }
+    }
+    let view = map.view_position;
+    image.draw_square(view.x as i32, view.y as i32, 9, VIEW_COLOR);
+    image
+}
+
+/// Render a region of the map using the terrain sprites, with 32 pixels per tile,
+/// with queen positions marked in red and the view position in white.
+///
+/// The sprites are all sprites of the game's sprite bank, in the given palette.
+/// Tiles without a terrain sprite, for instance in a modded bank lacking some, are left transparent.
+pub fn render_region(
+    map: &StoredMap,
+    sprites: &[Sprite],
+    palette: &Palette,
+    region: Rect,
+) -> RgbaImage {
+    let width = region.size.x as u32 * TILE_SIZE;
+    let height = region.size.y as u32 * TILE_SIZE;
+    let mut image = RgbaImage::new(width, height);
+    // terrain sprites are 192 to 355
+    let terrain_sprites = &sprites[192.min(sprites.len())..356.min(sprites.len())];
+    let mut missing = 0;
+    for ty in 0..region.size.y {
+        for tx in 0..region.size.x {
+            let position = region.top_left + Coord::new(tx, ty);
+            if !map.terrain.is_in_bounds(position) {
+                continue;
+            }
+            let sprite = match terrain_sprites.get(map.terrain.tile(position) as usize) {
+                Some(sprite) => sprite,
+                None => {
+                    missing += 1;
+                    continue;
+                }
+            };
+            for y in 0..TILE_SIZE.min(sprite.height) {
+                for x in 0..TILE_SIZE.min(sprite.width) {
+                    // terrain doesn't have transparency
+                    let pixel = &palette[sprite.pixel(x, y) as usize];
+                    let color = [pixel.r, pixel.g, pixel.b, 255];
+                    image.set_pixel(
+                        (tx as u32 * TILE_SIZE + x) as i32,
+                        (ty as u32 * TILE_SIZE + y) as i32,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+    if missing > 0 {
+        warn!("{missing} tiles have no terrain sprite, left transparent");
+    }
+    let mark = |image: &mut RgbaImage, position: Coord, color| {
+        let tile_size = TILE_SIZE as i32;
+        let x = (position.x - region.top_left.x) as i32 * tile_size + tile_size / 2;
+        let y = (position.y - region.top_left.y) as i32 * tile_size + tile_size / 2;
+        image.draw_square(x, y, tile_size, color);
+        image.draw_square(x, y, tile_size - 2, color);
+    };
+    for &position in &map.queen_positions {
+        mark(&mut image, position, QUEEN_COLOR);
+    }
+    mark(&mut image, map.view_position, VIEW_COLOR);
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legacy::terrain::TerrainMap;
+
+    #[test]
+    fn png_round_trip() {
+        let mut image = RgbaImage::new(3, 2);
+        image.set_pixel(1, 0, [1, 2, 3, 4]);
+        image.set_pixel(2, 1, [5, 6, 7, 255]);
+        let mut bytes = Vec::new();
+        image.write_png(&mut bytes).unwrap();
+        assert_eq!(RgbaImage::read_png(&bytes[..]).unwrap(), image);
+    }
+
+    #[test]
+    fn overview() {
+        let mut map = StoredMap {
+            header: None,
+            terrain: TerrainMap::new(1024, 1024),
+            queen_positions: vec![Coord::new(10, 20)],
+            view_position: Coord::new(512, 300),
+        };
+        map.terrain.set_tile(Coord::new(100, 200), 104);
+        let image = render_overview(&map);
+        assert_eq!((image.width, image.height), (1024, 1024));
+        assert_eq!(image.pixel(0, 0), terrain_color(TerrainType::Water));
+        assert_eq!(image.pixel(100, 200), terrain_color(TerrainType::Grass));
+        assert_eq!(image.pixel(10, 20), QUEEN_COLOR);
+        assert_eq!(image.pixel(512 - 4, 300), VIEW_COLOR);
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+
+    #[test]
+    fn region() {
+        let mut map = StoredMap {
+            header: None,
+            terrain: TerrainMap::new(16, 16),
+            queen_positions: vec![Coord::new(3, 2)],
+            view_position: Coord::new(8, 8),
+        };
+        map.terrain.set_tile(Coord::new(2, 2), 1);
+        map.terrain.set_tile(Coord::new(3, 3), 2);
+        // a bank with only the first two terrain sprites, of uniform entries 5 and 6
+        let sprite = |entry| Sprite {
+            width: TILE_SIZE,
+            height: TILE_SIZE,
+            x_extra: 0,
+            y_extra: 0,
+            pixels: vec![entry; (TILE_SIZE * TILE_SIZE) as usize],
+            padded: false,
+        };
+        let mut sprites = vec![sprite(0); 192];
+        sprites.extend([sprite(5), sprite(6)]);
+        let mut palette: Palette = [Default::default(); 256];
+        palette[5].r = 50;
+        palette[6].g = 60;
+        let region = Rect::new(Coord::new(2, 2), Coord::new(2, 2));
+        let image = render_region(&map, &sprites, &palette, region);
+        assert_eq!((image.width, image.height), (64, 64));
+        assert_eq!(image.pixel(16, 16), [0, 60, 0, 255]);
+        assert_eq!(image.pixel(16, 48), [50, 0, 0, 255]);
+        // the tile without sprite is transparent, the queen position marked
+        assert_eq!(image.pixel(48, 48), [0, 0, 0, 0]);
+        assert_eq!(image.pixel(32, 0), QUEEN_COLOR);
+    }
+}
@@ -0,0 +1,97 @@
+use super::terrain::TerrainType;
+
+/// Where a unit can move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mobility {
+    /// On sand and grass only
+    Land,
+    /// On sand, grass and water
+    Swimming,
+    /// Everywhere, including over resources
+    Flying,
+}
+impl Mobility {
+    /// Whether a unit with this mobility can enter a tile of the given terrain
+    pub fn can_enter(self, terrain: TerrainType) -> bool {
+        matches!(
+            (self, terrain),
+            (Mobility::Flying, _)
+                | (_, TerrainType::Grass | TerrainType::Sand)
+                | (Mobility::Swimming, TerrainType::Water)
+        )
+    }
+}
+
+/// The kinds of units
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnitKind {
+    #[default]
+    Worker,
+    Explorer,
+    Warrior,
+}
+impl UnitKind {
+    pub fn all() -> impl Iterator<Item = Self> {
+        [UnitKind::Worker, UnitKind::Explorer, UnitKind::Warrior].into_iter()
+    }
+    pub fn mobility(self) -> Mobility {
+        match self {
+            UnitKind::Worker => Mobility::Swimming,
+            UnitKind::Explorer => Mobility::Flying,
+            UnitKind::Warrior => Mobility::Land,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+    use crate::legacy::{
+        grid::{Coord, Grid, Grid2D},
+        resource::ResourceType,
+        terrain::TerrainMap,
+    };
+
+    #[test]
+    fn can_enter() {
+        use Mobility::*;
+        use TerrainType::*;
+        let enterable = |mobility: Mobility| {
+            [Grass, Sand, Water, Resource]
+                .into_iter()
+                .filter(|&terrain| mobility.can_enter(terrain))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(enterable(Land), [Grass, Sand]);
+        assert_eq!(enterable(Swimming), [Grass, Sand, Water]);
+        assert_eq!(enterable(Flying), [Grass, Sand, Water, Resource]);
+    }
+
+    #[test]
+    fn passable_for() {
+        let rng = &mut StepRng::new(0, 0);
+        // sand on the left, water on the right, with wheat in the middle of the sand
+        let mut types = Grid::new(8, 3, TerrainType::Sand);
+        for y in 0..3 {
+            for x in 4..8 {
+                types.set(Coord::new(x, y), TerrainType::Water);
+            }
+        }
+        let mut terrain = TerrainMap::from_types(&types, rng);
+        terrain.set_resource(Coord::new(1, 1), ResourceType::Wheat, rng);
+        let passable = |x, y, mobility| terrain.passable_for(Coord::new(x, y), mobility);
+        assert!(passable(0, 1, Mobility::Land));
+        assert!(!passable(1, 1, Mobility::Land));
+        assert!(!passable(1, 1, Mobility::Swimming));
+        assert!(passable(1, 1, Mobility::Flying));
+        assert!(!passable(6, 1, Mobility::Land));
+        assert!(passable(6, 1, Mobility::Swimming));
+        assert!(passable(6, 1, Mobility::Flying));
+        assert_eq!(
+            terrain.passable(Coord::new(6, 1)),
+            passable(6, 1, Mobility::Swimming)
+        );
+    }
+}
@@ -1,13 +1,22 @@
-mod palette;
-#[macro_use]
-mod array;
+pub mod assets;
 pub mod direction;
+pub mod palette;
 #[macro_use]
 pub mod grid;
 pub mod building;
+pub mod map_image;
+pub mod mobility;
 pub mod over_map;
 pub mod pheromone;
+#[cfg(feature = "bevy")]
+pub mod render;
+pub mod replay;
+pub mod resource;
+pub mod savegame;
+pub mod simulation;
+pub mod sprite_manifest;
 pub mod sprites;
 pub mod stored_map;
+pub mod team_color;
 pub mod terrain;
 pub mod unit;
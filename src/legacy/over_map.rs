@@ -1,21 +1,24 @@
-use bevy::prelude::Entity;
 use delegate::delegate;
 
-use super::grid::{Coord, Grid2D};
+use super::{
+    building::BuildingId,
+    grid::{Coord, Grid, Grid2D},
+    unit::UnitId,
+};
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum OverMapTile {
     #[default]
     Empty,
-    Unit(Entity),
-    Building(Entity),
+    Unit(UnitId),
+    Building(BuildingId),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct OverMap(pub Box<[[OverMapTile; 1024]; 1024]>);
-impl Default for OverMap {
-    fn default() -> Self {
-        Self(box_array![[OverMapTile::default(); 1024]; 1024])
+pub struct OverMap(pub Grid<OverMapTile>);
+impl OverMap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self(Grid::new(width, height, OverMapTile::default()))
     }
 }
 impl_grid2d_delegate!(OverMapTile, OverMap);
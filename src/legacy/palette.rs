@@ -1,4 +1,6 @@
-#[repr(C)]
+use std::{fs::File, io, io::Read, path::Path};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct PaletteEntry {
     pub r: u8,
     pub g: u8,
@@ -7,12 +9,275 @@ pub struct PaletteEntry {
 
 pub type Palette = [PaletteEntry; 256];
 
+/// The file formats of palettes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// 256 RGB entries, 768 bytes in total
+    Raw,
+    /// JASC (Paint Shop Pro) text palette, starting with "JASC-PAL"
+    Jasc,
+    /// GIMP text palette, starting with "GIMP Palette"
+    Gimp,
+}
+impl PaletteFormat {
+    /// Guess the format from the start of the file
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"JASC-PAL") {
+            PaletteFormat::Jasc
+        } else if bytes.starts_with(b"GIMP Palette") {
+            PaletteFormat::Gimp
+        } else {
+            PaletteFormat::Raw
+        }
+    }
+}
+
+/// An error while loading a palette, with line numbers starting at 1 for text formats
+#[derive(Debug)]
+pub enum PaletteLoadError {
+    /// The input could not be read
+    Io(io::Error),
+    /// A raw palette is not 768 bytes long
+    InvalidSize { size: usize },
+    /// A text palette is not valid UTF-8
+    NotText,
+    /// A line of a text palette is not as expected
+    InvalidLine { line: usize, content: String },
+    /// A text palette has more than 256 entries
+    TooManyEntries { line: usize },
+    /// A JASC palette has another number of entries than its header gives
+    WrongEntryCount { expected: usize, found: usize },
+}
+impl std::fmt::Display for PaletteLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use PaletteLoadError::*;
+        match self {
+            Io(error) => write!(f, "cannot read palette: {error}"),
+            InvalidSize { size } => {
+                write!(f, "raw palette has {size} bytes instead of 768")
+            }
+            NotText => write!(f, "text palette is not valid UTF-8"),
+            InvalidLine { line, content } => write!(f, "invalid line {line}: \"{content}\""),
+            TooManyEntries { line } => write!(f, "more than 256 entries at line {line}"),
+            WrongEntryCount { expected, found } => {
+                write!(f, "{found} entries instead of the {expected} given")
+            }
+        }
+    }
+}
+impl std::error::Error for PaletteLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PaletteLoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Convert 256 RGB entries, 768 bytes in total
+pub fn from_raw(bytes: &[u8]) -> Result<Palette, PaletteLoadError> {
+    if bytes.len() != 768 {
+        return Err(PaletteLoadError::InvalidSize { size: bytes.len() });
+    }
+    let mut palette = [PaletteEntry::default(); 256];
+    for (entry, rgb) in palette.iter_mut().zip(bytes.chunks_exact(3)) {
+        *entry = PaletteEntry {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+        };
+    }
+    Ok(palette)
+}
+
+/// Parse the "r g b" entries of a text palette after its header lines.
+/// Entries past the last one given are black.
+/// Also return the number of entries given.
+fn parse_entries<'a>(
+    lines: impl Iterator<Item = (usize, &'a str)>,
+) -> Result<(Palette, usize), PaletteLoadError> {
+    let mut palette = [PaletteEntry::default(); 256];
+    let mut count = 0;
+    for (line, content) in lines {
+        let trimmed = content.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let invalid_line = || PaletteLoadError::InvalidLine {
+            line: line + 1,
+            content: content.to_string(),
+        };
+        // GIMP entries might be followed by a name
+        let mut values = trimmed.split_whitespace().map(str::parse::<u8>);
+        let mut next = || values.next().and_then(Result::ok).ok_or_else(invalid_line);
+        let entry = PaletteEntry {
+            r: next()?,
+            g: next()?,
+            b: next()?,
+        };
+        *palette
+            .get_mut(count)
+            .ok_or(PaletteLoadError::TooManyEntries { line: line + 1 })? = entry;
+        count += 1;
+    }
+    Ok((palette, count))
+}
+
+/// Parse a JASC palette
+pub fn from_jasc(text: &str) -> Result<Palette, PaletteLoadError> {
+    let mut lines = text.lines().enumerate();
+    let mut header = |valid: &dyn Fn(&str) -> bool| match lines.next() {
+        Some((_, content)) if valid(content.trim()) => Ok(content.trim()),
+        Some((line, content)) => Err(PaletteLoadError::InvalidLine {
+            line: line + 1,
+            content: content.to_string(),
+        }),
+        None => Err(PaletteLoadError::InvalidLine {
+            line: text.lines().count() + 1,
+            content: String::new(),
+        }),
+    };
+    // signature, version and entry count
+    header(&|content| content.starts_with("JASC-PAL"))?;
+    header(&|content| content.starts_with("0100"))?;
+    let expected = header(&|content| content.parse::<usize>().is_ok())?
+        .parse()
+        .unwrap();
+    let (palette, found) = parse_entries(lines)?;
+    if found != expected {
+        return Err(PaletteLoadError::WrongEntryCount { expected, found });
+    }
+    Ok(palette)
+}
+
+/// Parse a GIMP palette
+pub fn from_gimp(text: &str) -> Result<Palette, PaletteLoadError> {
+    let header = |content: &str| {
+        let content = content.trim();
+        content.starts_with("GIMP Palette")
+            || content.starts_with("Name:")
+            || content.starts_with("Columns:")
+    };
+    let (palette, _) = parse_entries(
+        text.lines()
+            .enumerate()
+            .filter(|(_, content)| !header(content)),
+    )?;
+    Ok(palette)
+}
+
+/// Parse a palette in the given format
+pub fn parse(bytes: &[u8], format: PaletteFormat) -> Result<Palette, PaletteLoadError> {
+    let text = || std::str::from_utf8(bytes).map_err(|_| PaletteLoadError::NotText);
+    match format {
+        PaletteFormat::Raw => from_raw(bytes),
+        PaletteFormat::Jasc => from_jasc(text()?),
+        PaletteFormat::Gimp => from_gimp(text()?),
+    }
+}
+
+/// Read a palette, detecting its format
+pub fn read(mut input: impl Read) -> Result<Palette, PaletteLoadError> {
+    let mut bytes = Vec::new();
+    input
+        .read_to_end(&mut bytes)
+        .map_err(PaletteLoadError::Io)?;
+    parse(&bytes, PaletteFormat::detect(&bytes))
+}
+
+/// Load a palette from a file, detecting its format
+pub fn load_from_path(path: impl AsRef<Path>) -> Result<Palette, PaletteLoadError> {
+    read(io::BufReader::new(
+        File::open(path).map_err(PaletteLoadError::Io)?,
+    ))
+}
+
+/// The entry closest to an RGB color, optionally skipping entry 0 when it is reserved for transparency
+pub fn nearest_entry(palette: &Palette, color: [u8; 3], skip_first: bool) -> u8 {
+    let distance = |entry: &PaletteEntry| {
+        [entry.r, entry.g, entry.b]
+            .iter()
+            .zip(color)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    let first = skip_first as usize;
+    let (index, _) = palette[first..]
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| distance(entry))
+        .unwrap();
+    (index + first) as u8
+}
+
+#[cfg(feature = "embedded-assets")]
 lazy_static! {
     /// MacOS classic palette
-    pub static ref PALETTE: Palette = {
-        use std::mem;
-        let bytes = include_bytes!("../../assets/mac-palette.bin");
-        assert_eq!(mem::size_of::<Palette>(), bytes.len());
-        unsafe { mem::transmute(*bytes) }
-    };
+    pub static ref PALETTE: Palette =
+        from_raw(include_bytes!("../../assets/mac-palette.bin")).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        let raw: Vec<u8> = (0..=255).flat_map(|i| [i, 255 - i, 7]).collect();
+        let palette = parse(&raw, PaletteFormat::detect(&raw)).unwrap();
+        assert_eq!(palette[1], PaletteEntry { r: 1, g: 254, b: 7 });
+        assert!(matches!(
+            from_raw(&raw[..767]),
+            Err(PaletteLoadError::InvalidSize { size: 767 })
+        ));
+
+        let jasc = b"JASC-PAL\r\n0100\r\n3\r\n255 0 0\r\n0 255 0\r\n0 0 255\r\n";
+        assert_eq!(PaletteFormat::detect(jasc), PaletteFormat::Jasc);
+        let palette = parse(jasc, PaletteFormat::Jasc).unwrap();
+        assert_eq!(palette[2], PaletteEntry { r: 0, g: 0, b: 255 });
+        assert_eq!(palette[3], PaletteEntry::default());
+        assert!(matches!(
+            parse(b"JASC-PAL\n0100\n4\n255 0 0\n", PaletteFormat::Jasc),
+            Err(PaletteLoadError::WrongEntryCount {
+                expected: 4,
+                found: 1
+            })
+        ));
+        assert!(matches!(
+            parse(b"JASC-PAL\n0100\nmany\n255 0 0\n", PaletteFormat::Jasc),
+            Err(PaletteLoadError::InvalidLine { line: 3, .. })
+        ));
+
+        let gimp =
+            b"GIMP Palette\nName: Test\nColumns: 16\n#\n 10  20  30\tdark\n255 255 255 white\n";
+        assert_eq!(PaletteFormat::detect(gimp), PaletteFormat::Gimp);
+        let palette = parse(gimp, PaletteFormat::Gimp).unwrap();
+        assert_eq!(
+            palette[0],
+            PaletteEntry {
+                r: 10,
+                g: 20,
+                b: 30
+            }
+        );
+        assert_eq!(
+            palette[1],
+            PaletteEntry {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+
+        let invalid = b"GIMP Palette\n10 20\n";
+        assert!(matches!(
+            parse(invalid, PaletteFormat::Gimp),
+            Err(PaletteLoadError::InvalidLine { line: 2, .. })
+        ));
+        let too_many = format!("GIMP Palette\n{}", "1 2 3\n".repeat(257));
+        assert!(matches!(
+            parse(too_many.as_bytes(), PaletteFormat::Gimp),
+            Err(PaletteLoadError::TooManyEntries { line: 258 })
+        ));
+    }
 }
@@ -1,7 +1,10 @@
 use delegate::delegate;
-use num_enum::{IntoPrimitive, TryFromPrimitive};
 
-use super::grid::{Coord, Grid2D};
+pub use super::resource::ResourceType;
+use super::{
+    building::Building,
+    grid::{Coord, Grid, Grid2D, Rect},
+};
 
 /// A diffusion factor, in proportion/percentage
 pub struct DiffusionProportion(u16);
@@ -20,101 +23,348 @@ impl DiffusionProportion {
     }
 }
 
+/// Evaporation and diffusion of pheromone, done row by row as rows are contiguous in memory
 pub trait Dispersion: Grid2D<u16> {
-    /// Evaporate by 1
-    fn evaporate(&mut self) {
-        self.for_each(|intensity, _| intensity.saturating_sub(1))
-    }
-
-    /// Diffuse on x when passable, proportion is in percentage between 0 (0%) and 1024 (100%).
-    fn diffuse_x(&mut self, proportion: u16, passable: fn(Coord) -> bool) {
-        let proportion = proportion as i32;
-        let mut deltas = vec![0; Self::W];
-        for y in 0..Self::H as i16 {
-            // first pass, compute deltas to apply
-            for x in 0..(Self::W - 1) {
-                let cur_position = Coord::new(x as i16, y);
-                let next_position = Coord::new((x + 1) as i16, y);
-                if passable(cur_position) && passable(next_position) {
-                    let cur_value = self.get(cur_position) as i32;
-                    let next_value = self.get(next_position) as i32;
-                    let delta = next_value - cur_value;
-                    let amount = (delta * proportion) >> 10;
-                    deltas[x] += amount;
-                    deltas[x + 1] -= amount;
-                }
-            }
-            // second pass, replace values and reset deltas
-            #[allow(clippy::needless_range_loop)]
-            for x in 0..Self::W {
-                let position = Coord::new(x as i16, y);
-                let value = self.get(position) as i32;
-                self.set(position, (value + deltas[x]) as u16);
-                deltas[x] = 0;
+    fn row(&self, y: i16) -> &[u16];
+    fn row_mut(&mut self, y: i16) -> &mut [u16];
+
+    /// The area of the whole map
+    fn whole_area(&self) -> Rect {
+        Rect::new(
+            Coord::new(0, 0),
+            Coord::new(self.width() as i16, self.height() as i16),
+        )
+    }
+
+    /// Evaporate by 1 within an area
+    fn evaporate(&mut self, area: Rect) {
+        let columns = area.top_left.x as usize..(area.top_left.x + area.size.x) as usize;
+        for y in area.top_left.y..area.top_left.y + area.size.y {
+            for intensity in &mut self.row_mut(y)[columns.clone()] {
+                *intensity = intensity.saturating_sub(1);
             }
         }
     }
 
-    /// Diffuse on y when passable, proportion is in percentage between 0 (0%) and 1024 (100%).
-    fn diffuse_y(&mut self, proportion: u16, passable: fn(Coord) -> bool) {
-        let proportion = proportion as i32;
-        let mut deltas = vec![0; Self::H];
-        for x in 0..Self::W as i16 {
-            // first pass, compute deltas to apply
-            for y in 0..(Self::H - 1) {
-                let cur_position = Coord::new(x, y as i16);
-                let next_position = Coord::new(x, (y + 1) as i16);
-                if passable(cur_position) && passable(next_position) {
-                    let cur_value = self.get(cur_position) as i32;
-                    let next_value = self.get(next_position) as i32;
-                    let delta = next_value - cur_value;
-                    let amount = (delta * proportion) >> 10;
-                    deltas[y] += amount;
-                    deltas[y + 1] -= amount;
-                }
+    /// Diffuse on x when passable within an area, proportion is in percentage between 0 (0%) and 1024 (100%).
+    fn diffuse_x(&mut self, area: Rect, proportion: u16, passable: impl Fn(Coord) -> bool) {
+        diffuse_rows(self, area, proportion, passable, (true, false));
+    }
+
+    /// Diffuse on y when passable within an area, proportion is in percentage between 0 (0%) and 1024 (100%).
+    fn diffuse_y(&mut self, area: Rect, proportion: u16, passable: impl Fn(Coord) -> bool) {
+        diffuse_rows(self, area, proportion, passable, (false, true));
+    }
+
+    /// Diffuse on both x and y when passable within an area, proportion is in percentage between 0 (0%) and 1024 (100%).
+    /// Pheromone does not leave the area.
+    ///
+    /// Uses the linearity of diffusion, diffusing each row on x before it exchanges with the row above.
+    fn diffuse(&mut self, area: Rect, proportion: u16, passable: impl Fn(Coord) -> bool) {
+        diffuse_rows(self, area, proportion, passable, (true, true));
+    }
+}
+
+/// Sweep an area from top to bottom, diffusing each row on x then with the row above on y, as enabled by `axes`.
+/// Passability is evaluated once per tile, and the rows are only touched through buffers of the width of the area.
+fn diffuse_rows<M: Dispersion + ?Sized>(
+    map: &mut M,
+    area: Rect,
+    proportion: u16,
+    passable: impl Fn(Coord) -> bool,
+    axes: (bool, bool),
+) {
+    let proportion = proportion as i32;
+    let left = area.top_left.x;
+    let columns = left as usize..(left + area.size.x) as usize;
+    let (top, bottom) = (area.top_left.y, area.top_left.y + area.size.y);
+    if top >= bottom {
+        return;
+    }
+    let passable_row = |y: i16, row: &mut [bool]| {
+        for (x, passable_tile) in row.iter_mut().enumerate() {
+            *passable_tile = passable(Coord::new(left + x as i16, y));
+        }
+    };
+    let diffuse_x = |row: &mut [u16], passable_row: &[bool], deltas: &mut [i32]| {
+        // first pass, compute deltas to apply
+        for x in 0..row.len().saturating_sub(1) {
+            if passable_row[x] && passable_row[x + 1] {
+                let amount = ((row[x + 1] as i32 - row[x] as i32) * proportion) >> 10;
+                deltas[x] += amount;
+                deltas[x + 1] -= amount;
             }
-            // second pass, replace values and reset deltas
-            #[allow(clippy::needless_range_loop)]
-            for y in 0..Self::H {
-                let position = Coord::new(x, y as i16);
-                let value = self.get(position) as i32;
-                self.set(position, (value + deltas[y]) as u16);
-                deltas[y] = 0;
+        }
+        // second pass, replace values and reset deltas
+        for (value, delta) in row.iter_mut().zip(deltas.iter_mut()) {
+            *value = (*value as i32 + *delta) as u16;
+            *delta = 0;
+        }
+    };
+    let mut cur_passable = vec![false; columns.len()];
+    let mut next_passable = vec![false; columns.len()];
+    let mut deltas = vec![0; columns.len()];
+    // the deltas on y coming from the row above, applied once a row has exchanged with the row below
+    let mut from_above = vec![0; columns.len()];
+    // the values of the row below before the exchange
+    let mut below = vec![0; columns.len()];
+    passable_row(top, &mut cur_passable);
+    if axes.0 {
+        diffuse_x(
+            &mut map.row_mut(top)[columns.clone()],
+            &cur_passable,
+            &mut deltas,
+        );
+    }
+    for y in top..bottom {
+        if y + 1 < bottom {
+            passable_row(y + 1, &mut next_passable);
+            if axes.0 {
+                diffuse_x(
+                    &mut map.row_mut(y + 1)[columns.clone()],
+                    &next_passable,
+                    &mut deltas,
+                );
             }
+            below.copy_from_slice(&map.row(y + 1)[columns.clone()]);
+        } else {
+            next_passable.fill(false);
+        }
+        let row = &mut map.row_mut(y)[columns.clone()];
+        for x in 0..row.len() {
+            let amount = if axes.1 && cur_passable[x] && next_passable[x] {
+                ((below[x] as i32 - row[x] as i32) * proportion) >> 10
+            } else {
+                0
+            };
+            row[x] = (row[x] as i32 + from_above[x] + amount) as u16;
+            from_above[x] = -amount;
         }
+        std::mem::swap(&mut cur_passable, &mut next_passable);
     }
+}
 
-    /// Diffuse on both x and y when passable, proportion is in percentage between 0 (0%) and 1024 (100%).
-    ///
-    /// Uses the linearity of diffusion and calls first diffuse_x, then diffuse_y.
-    fn diffuse(&mut self, proportion: u16, passable: fn(Coord) -> bool) {
-        self.diffuse_x(proportion, passable);
-        self.diffuse_y(proportion, passable);
+/// The intensity of pheromone on each tile, knowing which area has some
+#[derive(Clone, Debug)]
+pub struct PheromoneMap {
+    intensities: Grid<u16>,
+    /// An area containing all tiles with pheromone, None if there is none
+    bounds: Option<Rect>,
+}
+impl PheromoneMap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::from_intensities(Grid::new(width, height, 0))
+    }
+    pub fn from_intensities(intensities: Grid<u16>) -> Self {
+        let mut map = Self {
+            intensities,
+            bounds: None,
+        };
+        map.bounds = map.find_bounds(map.whole_area());
+        map
+    }
+    pub fn intensities(&self) -> &Grid<u16> {
+        &self.intensities
+    }
+    /// Add pheromone on a tile, saturating at the maximum intensity
+    pub fn deposit(&mut self, position: Coord, amount: u16) {
+        let intensity = self.get(position).saturating_add(amount);
+        self.set(position, intensity);
+    }
+    /// Add pheromone on the tiles of a rect, clipped to the map
+    pub fn deposit_rect(&mut self, rect: Rect, amount: u16) {
+        let top_left = rect.top_left;
+        for y in top_left.y.max(0)..(top_left.y + rect.size.y).min(self.height() as i16) {
+            for x in top_left.x.max(0)..(top_left.x + rect.size.x).min(self.width() as i16) {
+                self.deposit(Coord::new(x, y), amount);
+            }
+        }
+    }
+    /// Add pheromone on the tiles covered by a building
+    pub fn deposit_footprint(&mut self, building: &Building, amount: u16) {
+        self.deposit_rect(building.footprint(), amount);
+    }
+    /// Evaporate and diffuse the pheromone between passable tiles.
+    /// Only the tiles having pheromone and their neighbours are visited, most maps being empty or almost.
+    pub fn disperse(&mut self, proportion: &DiffusionProportion, passable: impl Fn(Coord) -> bool) {
+        let bounds = match self.bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        self.evaporate(bounds);
+        // diffusion reaches one tile further on each axis
+        let left = (bounds.top_left.x - 1).max(0);
+        let top = (bounds.top_left.y - 1).max(0);
+        let right = (bounds.top_left.x + bounds.size.x + 1).min(self.width() as i16);
+        let bottom = (bounds.top_left.y + bounds.size.y + 1).min(self.height() as i16);
+        let area = Rect::new(
+            Coord::new(left, top),
+            Coord::new(right - left, bottom - top),
+        );
+        self.diffuse(area, proportion.0, passable);
+        self.bounds = self.find_bounds(area);
+    }
+    /// The smallest rect containing all the tiles of an area having pheromone
+    fn find_bounds(&self, area: Rect) -> Option<Rect> {
+        let left = area.top_left.x;
+        let columns = left as usize..(left + area.size.x) as usize;
+        let mut bounds: Option<(Coord, Coord)> = None;
+        for y in area.top_left.y..area.top_left.y + area.size.y {
+            let row = &self.row(y)[columns.clone()];
+            if let Some(first) = row.iter().position(|&intensity| intensity != 0) {
+                let last = row.iter().rposition(|&intensity| intensity != 0).unwrap();
+                let (first, last) = (left + first as i16, left + last as i16);
+                let (min, max) = bounds.get_or_insert((Coord::new(first, y), Coord::new(last, y)));
+                *min = Coord::new(min.x.min(first), min.y);
+                *max = Coord::new(max.x.max(last), y);
+            }
+        }
+        bounds.map(|(min, max)| Rect::new(min, max - min + Coord::new(1, 1)))
+    }
+}
+impl Grid2D<u16> for PheromoneMap {
+    delegate! {
+        to self.intensities {
+            fn width(&self) -> usize;
+            fn height(&self) -> usize;
+            fn get(&self, position: Coord) -> u16;
+        }
+    }
+    /// Set the intensity of a tile, extending the bounds of the pheromone to it
+    fn set(&mut self, position: Coord, value: u16) {
+        self.intensities.set(position, value);
+        if value != 0 {
+            let bounds = self
+                .bounds
+                .get_or_insert(Rect::new(position, Coord::new(1, 1)));
+            let top_left = Coord::new(
+                bounds.top_left.x.min(position.x),
+                bounds.top_left.y.min(position.y),
+            );
+            let bottom_right = Coord::new(
+                (bounds.top_left.x + bounds.size.x).max(position.x + 1),
+                (bounds.top_left.y + bounds.size.y).max(position.y + 1),
+            );
+            *bounds = Rect::new(top_left, bottom_right - top_left);
+        }
+    }
+}
+impl Dispersion for PheromoneMap {
+    delegate! {
+        to self.intensities {
+            fn row(&self, y: i16) -> &[u16];
+            fn row_mut(&mut self, y: i16) -> &mut [u16];
+        }
     }
 }
+/// Maps are equal when their intensities are, whatever their bounds
+impl PartialEq for PheromoneMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.intensities == other.intensities
+    }
+}
+impl Eq for PheromoneMap {}
+
+/// The pheromone emitted by resource tiles, one map per resource type shared by all teams
+pub type GatherMaps = [PheromoneMap; 4];
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
-#[repr(u8)]
-pub enum ResourceType {
-    Wheat = 0,
-    Wood,
-    Stone,
-    Algae,
+/// What the units following a pheromone map are up to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PheromoneKind {
+    /// Going to harvest the resource
+    Gather,
+    /// Bringing the harvested resource back
+    Collect,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct PheromoneMap(pub Box<[[u16; 1024]; 1024]>);
-impl Default for PheromoneMap {
-    fn default() -> Self {
-        Self(box_array![[0; 1024]; 1024])
+/// One of the pheromone maps of a team
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PheromoneChannel {
+    pub resource: ResourceType,
+    pub kind: PheromoneKind,
+}
+impl PheromoneChannel {
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..8).map(|index| Self::from_index(index).unwrap())
+    }
+    /// The index of the channel in the pheromone maps of a team
+    pub fn index(self) -> usize {
+        u8::from(self.resource) as usize * 2 + self.kind as usize
+    }
+    pub fn from_index(index: usize) -> Option<Self> {
+        let resource = ResourceType::try_from((index / 2) as u8).ok()?;
+        let kind = if index % 2 == 0 {
+            PheromoneKind::Gather
+        } else {
+            PheromoneKind::Collect
+        };
+        Some(Self { resource, kind })
+    }
+}
+
+/// Pheromone deposited on an area at every tick, until removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PheromoneSource {
+    pub channel: PheromoneChannel,
+    pub area: Rect,
+    pub amount: u16,
+}
+impl PheromoneSource {
+    pub fn point(channel: PheromoneChannel, position: Coord, amount: u16) -> Self {
+        Self {
+            channel,
+            area: Rect::new(position, Coord::new(1, 1)),
+            amount,
+        }
+    }
+    pub fn footprint(channel: PheromoneChannel, building: &Building, amount: u16) -> Self {
+        Self {
+            channel,
+            area: building.footprint(),
+            amount,
+        }
     }
 }
-impl_grid2d_delegate!(u16, PheromoneMap);
-impl Dispersion for PheromoneMap {}
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Team {
     // one per resource, and for each, gather/collect
     pub pheromone_maps: [PheromoneMap; 8],
+    pub sources: Vec<PheromoneSource>,
+}
+impl Team {
+    /// A team whose pheromone maps are empty, without sources
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            pheromone_maps: [(); 8].map(|_| PheromoneMap::new(width, height)),
+            sources: Vec::new(),
+        }
+    }
+    /// Deposit the pheromone of the sources
+    pub fn emit(&mut self) {
+        for source in &self.sources {
+            self.pheromone_maps[source.channel.index()].deposit_rect(source.area, source.amount);
+        }
+    }
+    /// The intensity units of the team follow on a channel, adding for gather channels the one of resources
+    pub fn intensity(
+        &self,
+        gather_maps: &GatherMaps,
+        channel: PheromoneChannel,
+        position: Coord,
+    ) -> u32 {
+        let shared = match channel.kind {
+            PheromoneKind::Gather => gather_maps[u8::from(channel.resource) as usize].get(position),
+            PheromoneKind::Collect => 0,
+        };
+        self.pheromone_map(channel).get(position) as u32 + shared as u32
+    }
+    pub fn pheromone_map(&self, channel: PheromoneChannel) -> &PheromoneMap {
+        &self.pheromone_maps[channel.index()]
+    }
+    pub fn pheromone_map_mut(&mut self, channel: PheromoneChannel) -> &mut PheromoneMap {
+        &mut self.pheromone_maps[channel.index()]
+    }
 }
 
 #[cfg(test)]
@@ -122,29 +372,36 @@ pub(crate) mod tests {
 
     use super::*;
 
-    impl<const W: usize, const H: usize> Dispersion for [[u16; W]; H] {}
+    impl<const W: usize, const H: usize> Dispersion for [[u16; W]; H] {
+        fn row(&self, y: i16) -> &[u16] {
+            &self[y as usize]
+        }
+        fn row_mut(&mut self, y: i16) -> &mut [u16] {
+            &mut self[y as usize]
+        }
+    }
 
     #[test]
     fn diffuse_x() {
         // normal
         let mut map = [[4, 8, 0]];
-        map.diffuse_x(512, |_| true);
+        map.diffuse_x(map.whole_area(), 512, |_| true);
         assert_eq!(map[0], [6, 2, 4]);
-        map.diffuse_x(256, |_| true);
+        map.diffuse_x(map.whole_area(), 256, |_| true);
         assert_eq!(map[0], [5, 3, 4]);
         // with blocking on the side
         let mut map = [[4, 4, 8, 0, 8]];
         let passable = |coord: Coord| coord.x > 0 && coord.x < 4;
-        map.diffuse_x(512, passable);
+        map.diffuse_x(map.whole_area(), 512, passable);
         assert_eq!(map[0], [4, 6, 2, 4, 8]);
-        map.diffuse_x(256, passable);
+        map.diffuse_x(map.whole_area(), 256, passable);
         assert_eq!(map[0], [4, 5, 3, 4, 8]);
         // with blocking in the middle
         let mut map = [[4, 8, 8, 0, 8]];
         let passable = |coord: Coord| coord.x != 2;
-        map.diffuse_x(512, passable);
+        map.diffuse_x(map.whole_area(), 512, passable);
         assert_eq!(map[0], [6, 6, 8, 4, 4]);
-        map.diffuse_x(256, passable);
+        map.diffuse_x(map.whole_area(), 256, passable);
         assert_eq!(map[0], [6, 6, 8, 4, 4]);
     }
 
@@ -152,24 +409,24 @@ pub(crate) mod tests {
     fn diffuse_y() {
         // normal
         let mut map = [[4], [8], [0]];
-        map.diffuse_y(512, |_| true);
+        map.diffuse_y(map.whole_area(), 512, |_| true);
         assert_eq!(map[0][0], 6);
         assert_eq!(map[1][0], 2);
         assert_eq!(map[2][0], 4);
-        map.diffuse_y(256, |_| true);
+        map.diffuse_y(map.whole_area(), 256, |_| true);
         assert_eq!(map[0][0], 5);
         assert_eq!(map[1][0], 3);
         assert_eq!(map[2][0], 4);
         // with blocking on the side
         let mut map = [[4], [4], [8], [0], [8]];
         let passable = |coord: Coord| coord.y > 0 && coord.y < 4;
-        map.diffuse_y(512, passable);
+        map.diffuse_y(map.whole_area(), 512, passable);
         assert_eq!(map[0][0], 4);
         assert_eq!(map[1][0], 6);
         assert_eq!(map[2][0], 2);
         assert_eq!(map[3][0], 4);
         assert_eq!(map[4][0], 8);
-        map.diffuse_y(256, passable);
+        map.diffuse_y(map.whole_area(), 256, passable);
         assert_eq!(map[0][0], 4);
         assert_eq!(map[1][0], 5);
         assert_eq!(map[2][0], 3);
@@ -178,17 +435,64 @@ pub(crate) mod tests {
         // with blocking in the middle
         let mut map = [[4], [8], [8], [0], [8]];
         let passable = |coord: Coord| coord.y != 2;
-        map.diffuse_y(512, passable);
+        map.diffuse_y(map.whole_area(), 512, passable);
         assert_eq!(map[0][0], 6);
         assert_eq!(map[1][0], 6);
         assert_eq!(map[2][0], 8);
         assert_eq!(map[3][0], 4);
         assert_eq!(map[4][0], 4);
-        map.diffuse_y(256, passable);
+        map.diffuse_y(map.whole_area(), 256, passable);
         assert_eq!(map[0][0], 6);
         assert_eq!(map[1][0], 6);
         assert_eq!(map[2][0], 8);
         assert_eq!(map[3][0], 4);
         assert_eq!(map[4][0], 4);
     }
+
+    #[test]
+    fn deposit_rect_clipped() {
+        let mut map = PheromoneMap::new(4, 3);
+        map.deposit_rect(Rect::new(Coord::new(-2, -1), Coord::new(3, 2)), 5);
+        map.deposit_rect(Rect::new(Coord::new(3, 1), Coord::new(4, 4)), 7);
+        map.deposit_rect(Rect::new(Coord::new(4, 0), Coord::new(2, 2)), 9);
+        let expected = [[5, 0, 0, 0], [0, 0, 0, 7], [0, 0, 0, 7]];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, &intensity) in row.iter().enumerate() {
+                assert_eq!(map.get(Coord::new(x as i16, y as i16)), intensity);
+            }
+        }
+    }
+
+    #[test]
+    fn disperse_within_bounds() {
+        let proportion = DiffusionProportion::from_percent(50);
+        let passable = |coord: Coord| coord.x != 9 || coord.y == 12;
+        let mut map = PheromoneMap::new(20, 16);
+        map.deposit(Coord::new(8, 5), 1000);
+        map.deposit(Coord::new(11, 7), 300);
+        // dispersing on the whole map, with the bounds left aside
+        let mut whole = map.clone();
+        for _ in 0..10 {
+            map.disperse(&proportion, passable);
+            let area = whole.whole_area();
+            whole.evaporate(area);
+            whole.diffuse(area, proportion.0, passable);
+            assert_eq!(map, whole);
+        }
+        // the pheromone spread but did not reach the whole map
+        let bounds = map.bounds.unwrap();
+        assert!(bounds.size.x > 4 && bounds.size.x < 20);
+        assert!(bounds.size.y > 3 && bounds.size.y < 16);
+        assert_eq!(map.find_bounds(map.whole_area()), Some(bounds));
+    }
+
+    #[test]
+    fn channel_index() {
+        for (index, channel) in PheromoneChannel::all().enumerate() {
+            assert_eq!(channel.index(), index);
+            assert_eq!(PheromoneChannel::from_index(index), Some(channel));
+        }
+        assert_eq!(PheromoneChannel::all().count(), 8);
+        assert_eq!(PheromoneChannel::from_index(8), None);
+    }
 }
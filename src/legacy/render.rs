@@ -0,0 +1,180 @@
+use bevy::{
+    math::{IVec3, UVec2},
+    prelude::{Commands, Component, Entity, Handle, Image, Local, Query, Res, ResMut, Transform},
+    sprite::{Anchor, SpriteSheetBundle, TextureAtlas, TextureAtlasSprite},
+};
+use bevy_simple_tilemap::prelude::{Tile, TileMap};
+
+use super::{
+    building::BuildingId,
+    grid::{grid_to_world, grid_to_world_with_delta, Coord, Grid2D},
+    simulation::Simulation,
+    terrain::TerrainMap,
+    unit::{TeamId, UnitId},
+};
+
+#[derive(Default)]
+pub struct UnitSprites {
+    pub texture_atlas: Handle<TextureAtlas>,
+    /// The sprites of all teams, team after team
+    pub sprites: Vec<((Handle<Image>, UVec2), usize)>,
+    /// The anchor of each sprite, from its hot-spot offsets
+    pub anchors: Vec<Anchor>,
+}
+impl UnitSprites {
+    /// The index in the texture atlas of a sprite in the colors of a team
+    pub fn atlas_index(&self, team: TeamId, index: usize) -> usize {
+        self.sprites[team.0 as usize * self.anchors.len() + index].1
+    }
+}
+
+#[derive(Default)]
+pub struct BuildingSprites {
+    pub texture_atlas: Handle<TextureAtlas>,
+    /// The sprites of all teams, team after team
+    pub sprites: Vec<((Handle<Image>, UVec2), usize)>,
+    /// The anchor of each sprite, from its hot-spot offsets
+    pub anchors: Vec<Anchor>,
+}
+impl BuildingSprites {
+    /// The index in the texture atlas of a sprite in the colors of a team
+    pub fn atlas_index(&self, team: TeamId, index: usize) -> usize {
+        self.sprites[team.0 as usize * self.anchors.len() + index].1
+    }
+}
+
+/// The index in the terrain texture atlas of each terrain tile
+#[derive(Default)]
+pub struct TerrainSprites(pub Vec<u32>);
+impl TerrainSprites {
+    /// The tiles of the tilemap showing the given terrain
+    pub fn tiles(&self, terrain: &TerrainMap) -> Vec<(IVec3, Option<Tile>)> {
+        let (width, height) = (terrain.width() as i16, terrain.height() as i16);
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| Coord::new(x, y)))
+            .map(|position| self.tile(terrain, position))
+            .collect()
+    }
+    fn tile(&self, terrain: &TerrainMap, position: Coord) -> (IVec3, Option<Tile>) {
+        (
+            IVec3::new(position.x as i32, -(position.y as i32), 0),
+            Some(Tile {
+                sprite_index: self.0[terrain.tile(position) as usize],
+                ..Default::default()
+            }),
+        )
+    }
+}
+
+/// The sprite showing a unit of the simulation
+#[derive(Component)]
+pub struct UnitSprite(pub UnitId);
+
+/// The sprite showing a building of the simulation
+#[derive(Component)]
+pub struct BuildingSprite(pub BuildingId);
+
+/// Spawn sprites for the units and buildings added to the simulation since the last call,
+/// and despawn the ones of units and buildings that no longer exist, for instance after seeking back a replay
+pub fn sync_sprites(
+    mut commands: Commands,
+    simulation: Res<Simulation>,
+    unit_sprites: Res<UnitSprites>,
+    building_sprites: Res<BuildingSprites>,
+    unit_query: Query<(Entity, &UnitSprite)>,
+    building_query: Query<(Entity, &BuildingSprite)>,
+    mut spawned: Local<(usize, usize)>,
+) {
+    let (unit_count, building_count) = &mut *spawned;
+    if simulation.units.len() < *unit_count {
+        for (entity, unit_sprite) in unit_query.iter() {
+            if unit_sprite.0 .0 as usize >= simulation.units.len() {
+                commands.entity(entity).despawn();
+            }
+        }
+        *unit_count = simulation.units.len();
+    }
+    if simulation.buildings.len() < *building_count {
+        for (entity, building_sprite) in building_query.iter() {
+            if building_sprite.0 .0 as usize >= simulation.buildings.len() {
+                commands.entity(entity).despawn();
+            }
+        }
+        *building_count = simulation.buildings.len();
+    }
+    for (index, unit) in simulation.units.iter().enumerate().skip(*unit_count) {
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                sprite: TextureAtlasSprite {
+                    index: unit_sprites.atlas_index(unit.team, 0),
+                    anchor: unit_sprites.anchors[0].clone(),
+                    ..Default::default()
+                },
+                texture_atlas: unit_sprites.texture_atlas.clone(),
+                transform: Transform::from_translation(grid_to_world(unit.position)),
+                ..Default::default()
+            })
+            .insert(UnitSprite(UnitId(index as u32)));
+    }
+    *unit_count = simulation.units.len();
+    for (index, building) in simulation
+        .buildings
+        .iter()
+        .enumerate()
+        .skip(*building_count)
+    {
+        let image_index = building.ty.image_index();
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                sprite: TextureAtlasSprite {
+                    index: building_sprites.atlas_index(building.team, image_index),
+                    anchor: building_sprites.anchors[image_index].clone(),
+                    ..Default::default()
+                },
+                texture_atlas: building_sprites.texture_atlas.clone(),
+                transform: Transform::from_translation(grid_to_world(building.position)),
+                ..Default::default()
+            })
+            .insert(BuildingSprite(BuildingId(index as u32)));
+    }
+    *building_count = simulation.buildings.len();
+}
+
+/// Place and animate the unit sprites following their unit
+pub fn update_unit_sprites(
+    simulation: Res<Simulation>,
+    unit_sprites: Res<UnitSprites>,
+    mut query: Query<(&UnitSprite, &mut TextureAtlasSprite, &mut Transform)>,
+) {
+    for (unit_sprite, mut sprite, mut transform) in query.iter_mut() {
+        // the unit might have been removed since its sprite was last synced
+        let unit = match simulation.units.get(unit_sprite.0 .0 as usize) {
+            Some(unit) => unit,
+            None => continue,
+        };
+        let (delta_position, index) = unit.animation();
+        transform.translation = grid_to_world_with_delta(unit.position, delta_position);
+        sprite.index = unit_sprites.atlas_index(unit.team, index);
+        sprite.anchor = unit_sprites.anchors[index].clone();
+    }
+}
+
+/// Update the tiles of the terrain that changed in the simulation since the last frame
+pub fn update_terrain_tiles(
+    mut simulation: ResMut<Simulation>,
+    terrain_sprites: Res<TerrainSprites>,
+    mut query: Query<&mut TileMap>,
+) {
+    let changed_tiles = simulation.terrain.take_changed_tiles();
+    if changed_tiles.is_empty() {
+        return;
+    }
+    let terrain = &simulation.terrain;
+    let changes: Vec<_> = changed_tiles
+        .into_iter()
+        .map(|position| terrain_sprites.tile(terrain, position))
+        .collect();
+    for mut tilemap in query.iter_mut() {
+        tilemap.set_tiles(changes.iter().cloned());
+    }
+}
@@ -0,0 +1,343 @@
+use std::io::{self, Read, Write};
+
+use super::{
+    grid::Coord,
+    mobility::UnitKind,
+    pheromone::PheromoneChannel,
+    simulation::{Command, Simulation},
+    stored_map::MapLoadError,
+    unit::{MovePolicy, TeamId, UnitId},
+};
+
+/// The first bytes of a replay file
+pub const REPLAY_SIGNATURE: [u8; 4] = *b"G1RP";
+
+/// The version of the replay format written by `Replay::write`
+pub const REPLAY_VERSION: u8 = 1;
+
+/// Everything needed to re-simulate a game: its seed, its map and the commands of the players
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Replay {
+    pub seed: u64,
+    /// The map file, as stored on disk
+    pub map: Vec<u8>,
+    /// The commands with the tick they were applied at, in increasing tick order
+    pub commands: Vec<(u64, Command)>,
+}
+
+#[derive(Debug)]
+pub enum ReplayLoadError {
+    Io(io::Error),
+    InvalidSignature { signature: [u8; 4] },
+    UnsupportedVersion { version: u8 },
+    UnorderedCommand { index: usize, tick: u64 },
+    UnknownCommand { index: usize, tag: u8 },
+    UnknownUnitKind { index: usize, kind: u8 },
+    UnknownMovePolicy { index: usize, policy: u8 },
+    UnknownChannel { index: usize, channel: u8 },
+}
+impl std::fmt::Display for ReplayLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ReplayLoadError::*;
+        match self {
+            Io(error) => write!(f, "cannot read replay: {error}"),
+            InvalidSignature { signature } => {
+                write!(f, "invalid replay signature {signature:?}")
+            }
+            UnsupportedVersion { version } => {
+                write!(f, "unsupported replay version {version}")
+            }
+            UnorderedCommand { index, tick } => {
+                write!(
+                    f,
+                    "command {index} at tick {tick} is before the previous one"
+                )
+            }
+            UnknownCommand { index, tag } => {
+                write!(f, "unknown type {tag} of command {index}")
+            }
+            UnknownUnitKind { index, kind } => {
+                write!(f, "unknown unit kind {kind} in command {index}")
+            }
+            UnknownMovePolicy { index, policy } => {
+                write!(f, "unknown move policy {policy} in command {index}")
+            }
+            UnknownChannel { index, channel } => {
+                write!(f, "unknown pheromone channel {channel} in command {index}")
+            }
+        }
+    }
+}
+impl std::error::Error for ReplayLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayLoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for ReplayLoadError {
+    fn from(error: io::Error) -> Self {
+        ReplayLoadError::Io(error)
+    }
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl Replay {
+    pub fn new(seed: u64, map: Vec<u8>) -> Self {
+        Self {
+            seed,
+            map,
+            commands: Vec::new(),
+        }
+    }
+    /// Add a command applied at tick, which must not be before the last recorded one
+    pub fn record(&mut self, tick: u64, command: Command) {
+        if let Some(&(last_tick, _)) = self.commands.last() {
+            assert!(tick >= last_tick, "commands must be recorded in tick order");
+        }
+        self.commands.push((tick, command));
+    }
+    /// Write the replay, integers being little endian
+    pub fn write(&self, mut output: impl Write) -> io::Result<()> {
+        output.write_all(&REPLAY_SIGNATURE)?;
+        output.write_all(&[REPLAY_VERSION])?;
+        output.write_all(&self.seed.to_le_bytes())?;
+        output.write_all(&(self.map.len() as u32).to_le_bytes())?;
+        output.write_all(&self.map)?;
+        output.write_all(&(self.commands.len() as u32).to_le_bytes())?;
+        for (tick, command) in &self.commands {
+            output.write_all(&tick.to_le_bytes())?;
+            match *command {
+                Command::SpawnUnit {
+                    position,
+                    kind,
+                    team,
+                } => {
+                    let kind = UnitKind::all().position(|k| k == kind).unwrap() as u8;
+                    output.write_all(&[0])?;
+                    output.write_all(&position.x.to_le_bytes())?;
+                    output.write_all(&position.y.to_le_bytes())?;
+                    output.write_all(&[kind, team.0])?;
+                }
+                Command::SetPolicy { unit, policy } => {
+                    output.write_all(&[1])?;
+                    output.write_all(&unit.0.to_le_bytes())?;
+                    match policy {
+                        MovePolicy::RandomWalk => output.write_all(&[0])?,
+                        MovePolicy::Climb(channel) => {
+                            output.write_all(&[1, channel.index() as u8])?
+                        }
+                        MovePolicy::Descend(channel) => {
+                            output.write_all(&[2, channel.index() as u8])?
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn read(mut input: impl Read) -> Result<Self, ReplayLoadError> {
+        let input = &mut input;
+        let signature = read_bytes(input)?;
+        if signature != REPLAY_SIGNATURE {
+            return Err(ReplayLoadError::InvalidSignature { signature });
+        }
+        let [version] = read_bytes(input)?;
+        if version != REPLAY_VERSION {
+            return Err(ReplayLoadError::UnsupportedVersion { version });
+        }
+        let seed = u64::from_le_bytes(read_bytes(input)?);
+        let map_len = u32::from_le_bytes(read_bytes(input)?) as usize;
+        let mut map = Vec::new();
+        input.by_ref().take(map_len as u64).read_to_end(&mut map)?;
+        if map.len() != map_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let command_count = u32::from_le_bytes(read_bytes(input)?) as usize;
+        let mut commands: Vec<(u64, Command)> = Vec::new();
+        for index in 0..command_count {
+            let tick = u64::from_le_bytes(read_bytes(input)?);
+            if commands
+                .last()
+                .map_or(false, |&(last_tick, _)| tick < last_tick)
+            {
+                return Err(ReplayLoadError::UnorderedCommand { index, tick });
+            }
+            let command = match read_bytes(input)? {
+                [0] => {
+                    let x = i16::from_le_bytes(read_bytes(input)?);
+                    let y = i16::from_le_bytes(read_bytes(input)?);
+                    let [kind, team] = read_bytes(input)?;
+                    Command::SpawnUnit {
+                        position: Coord::new(x, y),
+                        kind: UnitKind::all()
+                            .nth(kind as usize)
+                            .ok_or(ReplayLoadError::UnknownUnitKind { index, kind })?,
+                        team: TeamId(team),
+                    }
+                }
+                [1] => {
+                    let unit = UnitId(u32::from_le_bytes(read_bytes(input)?));
+                    let policy = match read_bytes(input)? {
+                        [0] => MovePolicy::RandomWalk,
+                        [policy @ (1 | 2)] => {
+                            let [channel] = read_bytes(input)?;
+                            let channel = PheromoneChannel::from_index(channel as usize)
+                                .ok_or(ReplayLoadError::UnknownChannel { index, channel })?;
+                            if policy == 1 {
+                                MovePolicy::Climb(channel)
+                            } else {
+                                MovePolicy::Descend(channel)
+                            }
+                        }
+                        [policy] => {
+                            return Err(ReplayLoadError::UnknownMovePolicy { index, policy })
+                        }
+                    };
+                    Command::SetPolicy { unit, policy }
+                }
+                [tag] => return Err(ReplayLoadError::UnknownCommand { index, tag }),
+            };
+            commands.push((tick, command));
+        }
+        Ok(Self {
+            seed,
+            map,
+            commands,
+        })
+    }
+}
+
+/// Re-simulates a replay, applying its commands at their tick and allowing to seek to any tick
+#[derive(Clone, Debug)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    /// The simulation before the first tick, to restart from when seeking backward
+    initial: Simulation,
+    next_command: usize,
+}
+impl ReplayPlayer {
+    /// Decode the map of the replay, returning the player and the simulation to play it on
+    pub fn new(replay: Replay) -> Result<(Self, Simulation), MapLoadError> {
+        let initial = Simulation::load(replay.map.as_slice(), replay.seed)?;
+        let simulation = initial.clone();
+        let player = Self {
+            replay,
+            initial,
+            next_command: 0,
+        };
+        Ok((player, simulation))
+    }
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+    /// The tick of the last command, after which the game continues without player input
+    pub fn last_command_tick(&self) -> u64 {
+        self.replay.commands.last().map_or(0, |&(tick, _)| tick)
+    }
+    /// Apply the commands of the current tick, then advance the simulation by one tick
+    pub fn step(&mut self, simulation: &mut Simulation) {
+        let tick = simulation.tick();
+        let commands = &self.replay.commands[self.next_command..];
+        for (_, command) in commands.iter().take_while(|&&(at, _)| at <= tick) {
+            simulation.apply(command);
+            self.next_command += 1;
+        }
+        simulation.step();
+    }
+    /// Bring the simulation to tick, restarting from the beginning when seeking backward
+    pub fn seek(&mut self, simulation: &mut Simulation, tick: u64) {
+        if tick < simulation.tick() {
+            let mut initial = self.initial.clone();
+            initial.terrain.record_changes_from(&simulation.terrain);
+            *simulation = initial;
+            self.next_command = 0;
+        }
+        while simulation.tick() < tick {
+            self.step(simulation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legacy::{
+        grid::Grid2D,
+        stored_map::{self, StoredMap},
+        terrain::{TerrainMap, TerrainType},
+    };
+
+    #[test]
+    fn record_and_play() {
+        let mut terrain = TerrainMap::new(1024, 1024);
+        for y in 0..32 {
+            for x in 0..32 {
+                terrain.set(Coord::new(x, y), TerrainType::Sand);
+            }
+        }
+        let map = StoredMap {
+            header: None,
+            terrain,
+            queen_positions: vec![Coord::new(4, 4)],
+            view_position: Coord::new(0, 0),
+        };
+        let mut map_bytes = Vec::new();
+        stored_map::save(&map, &mut map_bytes).unwrap();
+
+        // record a game with commands given along the way
+        let mut replay = Replay::new(42, map_bytes);
+        let mut simulation = Simulation::load(replay.map.as_slice(), replay.seed).unwrap();
+        for tick in 0..300 {
+            if tick % 100 == 10 {
+                let command = Command::SpawnUnit {
+                    position: Coord::new(20, 10 + tick as i16 / 100),
+                    kind: UnitKind::Warrior,
+                    team: TeamId(1),
+                };
+                replay.record(simulation.tick(), command);
+                simulation.apply(&command);
+            }
+            if tick == 150 {
+                let channel = PheromoneChannel::from_index(3).unwrap();
+                let command = Command::SetPolicy {
+                    unit: UnitId(1),
+                    policy: MovePolicy::Descend(channel),
+                };
+                replay.record(simulation.tick(), command);
+                simulation.apply(&command);
+            }
+            simulation.step();
+        }
+        let mut bytes = Vec::new();
+        replay.write(&mut bytes).unwrap();
+        let replay = Replay::read(bytes.as_slice()).unwrap();
+        assert_eq!(replay.commands.len(), 4);
+
+        // playing gives the same game, also after seeking backward
+        let (mut player, mut played) = ReplayPlayer::new(replay).unwrap();
+        assert_eq!(player.last_command_tick(), 210);
+        player.seek(&mut played, 300);
+        assert_eq!(played.units, simulation.units);
+        player.seek(&mut played, 50);
+        assert_eq!(played.units.len(), 2);
+        player.seek(&mut played, 300);
+        assert_eq!(played.units, simulation.units);
+
+        assert!(matches!(
+            Replay::read(&bytes[..20]),
+            Err(ReplayLoadError::Io(_))
+        ));
+        bytes[4] = 2;
+        assert!(matches!(
+            Replay::read(bytes.as_slice()),
+            Err(ReplayLoadError::UnsupportedVersion { version: 2 })
+        ));
+    }
+}
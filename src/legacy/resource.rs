@@ -0,0 +1,202 @@
+use delegate::delegate;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use rand::{seq::IteratorRandom, Rng};
+
+use super::{
+    grid::{Coord, Grid, Grid2D},
+    terrain::{TerrainMap, TerrainType},
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ResourceType {
+    Wheat = 0,
+    Wood,
+    Stone,
+    Algae,
+}
+impl ResourceType {
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..4).map(|index| Self::try_from(index).unwrap())
+    }
+    /// The terrain the resource lies on, which it leaves when depleted
+    pub fn ground(self) -> TerrainType {
+        match self {
+            ResourceType::Wheat | ResourceType::Wood => TerrainType::Grass,
+            ResourceType::Stone => TerrainType::Sand,
+            ResourceType::Algae => TerrainType::Water,
+        }
+    }
+    /// The amount of a fully grown tile of the resource
+    pub fn max_amount(self) -> u8 {
+        match self {
+            ResourceType::Wheat | ResourceType::Algae => 8,
+            ResourceType::Wood => 16,
+            ResourceType::Stone => 32,
+        }
+    }
+    /// Whether the resource regrows and spreads, stone does not
+    pub fn grows(self) -> bool {
+        self != ResourceType::Stone
+    }
+}
+
+/// The probabilities for a tile to grow, each time `ResourceMap::grow` is called
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthRates {
+    /// Probability for a tile that is not fully grown to grow by one
+    pub regrow: f64,
+    /// Probability for a fully grown tile to spread to a neighbour of its ground
+    pub spread: f64,
+}
+impl Default for GrowthRates {
+    fn default() -> Self {
+        Self {
+            regrow: 1. / 64.,
+            spread: 1. / 256.,
+        }
+    }
+}
+
+/// The remaining amount of resource of each tile, 0 on tiles without resource
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceMap(pub Grid<u8>);
+impl ResourceMap {
+    /// Create a map with fully grown resources on the resource tiles of the terrain
+    pub fn new(terrain: &TerrainMap) -> Self {
+        let mut amounts = Grid::new(terrain.width(), terrain.height(), 0);
+        amounts.for_each(|_, position| {
+            terrain
+                .resource_type(position)
+                .map_or(0, ResourceType::max_amount)
+        });
+        Self(amounts)
+    }
+    /// Take one unit of resource from a tile, returning its type if there was any.
+    /// A depleted tile becomes the ground of its resource.
+    pub fn harvest(
+        &mut self,
+        terrain: &mut TerrainMap,
+        position: Coord,
+        rng: &mut impl Rng,
+    ) -> Option<ResourceType> {
+        let ty = terrain.resource_type(position)?;
+        let amount = self.get(position);
+        if amount == 0 {
+            return None;
+        }
+        self.set(position, amount - 1);
+        if amount == 1 {
+            terrain.set_type(position, ty.ground(), rng);
+        }
+        Some(ty)
+    }
+    /// Let resources regrow, and fully grown ones spread to neighbours of their ground
+    pub fn grow(&mut self, terrain: &mut TerrainMap, rates: &GrowthRates, rng: &mut impl Rng) {
+        let mut spreads = Vec::new();
+        for y in 0..self.height() as i16 {
+            for x in 0..self.width() as i16 {
+                let position = Coord::new(x, y);
+                let ty = match terrain.resource_type(position) {
+                    Some(ty) if ty.grows() => ty,
+                    _ => continue,
+                };
+                let amount = self.get(position);
+                if amount < ty.max_amount() {
+                    if rng.gen_bool(rates.regrow) {
+                        self.set(position, amount + 1);
+                    }
+                } else if rng.gen_bool(rates.spread) {
+                    let target = (-1..=1)
+                        .flat_map(|dy| (-1..=1).map(move |dx| position + Coord::new(dx, dy)))
+                        .filter(|&target| {
+                            terrain.is_in_bounds(target) && terrain.get(target) == ty.ground()
+                        })
+                        .choose(rng);
+                    if let Some(target) = target {
+                        spreads.push((target, ty));
+                    }
+                }
+            }
+        }
+        // spread after growing, so that new tiles do not grow in the same call
+        for (position, ty) in spreads {
+            if terrain.get(position) == ty.ground() {
+                terrain.set_resource(position, ty, rng);
+                self.set(position, 1);
+            }
+        }
+    }
+}
+impl_grid2d_delegate!(u8, ResourceMap);
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::legacy::terrain::TerrainClass;
+
+    #[test]
+    fn harvest_and_grow() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        // grass on the left half, water on the right half
+        let mut terrain = TerrainMap::new(8, 8);
+        for y in 0..8 {
+            for x in 0..4 {
+                terrain.set_type(Coord::new(x, y), TerrainType::Grass, rng);
+            }
+        }
+        let wheat = Coord::new(1, 1);
+        let stone = Coord::new(6, 6);
+        terrain.set_resource(wheat, ResourceType::Wheat, rng);
+        terrain.set_resource(stone, ResourceType::Stone, rng);
+        assert_eq!(
+            terrain.class(wheat),
+            TerrainClass::resource(ResourceType::Wheat)
+        );
+        let mut resources = ResourceMap::new(&terrain);
+        assert_eq!(resources.get(wheat), 8);
+        assert_eq!(resources.get(stone), 32);
+        assert_eq!(resources.get(Coord::new(0, 0)), 0);
+
+        // deplete the wheat, which leaves grass
+        for _ in 0..8 {
+            assert_eq!(
+                resources.harvest(&mut terrain, wheat, rng),
+                Some(ResourceType::Wheat)
+            );
+        }
+        assert_eq!(terrain.get(wheat), TerrainType::Grass);
+        assert_eq!(resources.harvest(&mut terrain, wheat, rng), None);
+
+        // regrow and spread wheat, only onto grass, while stone does not grow
+        terrain.set_resource(wheat, ResourceType::Wheat, rng);
+        resources.set(wheat, 1);
+        let rates = GrowthRates {
+            regrow: 0.5,
+            spread: 0.5,
+        };
+        for _ in 0..100 {
+            resources.grow(&mut terrain, &rates, rng);
+        }
+        assert_eq!(resources.get(wheat), 8);
+        let mut wheat_count = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                let position = Coord::new(x, y);
+                match terrain.resource_type(position) {
+                    Some(ResourceType::Wheat) => {
+                        assert!(x < 4);
+                        assert!(resources.get(position) > 0);
+                        wheat_count += 1;
+                    }
+                    Some(_) => assert_eq!(position, stone),
+                    None => assert_eq!(resources.get(position), 0),
+                }
+            }
+        }
+        assert!(wheat_count > 1);
+        assert_eq!(resources.get(stone), 32);
+    }
+}
@@ -0,0 +1,681 @@
+use std::io::{self, BufReader, Read, Write};
+
+use rand::SeedableRng;
+
+use super::{
+    building::{
+        Building, BuildingId, BuildingLevel, BuildingType, ConstructionSiteType, WonderLevel,
+    },
+    direction::Direction,
+    grid::{Coord, Grid, Grid2D, Rect},
+    mobility::UnitKind,
+    over_map::{OverMap, OverMapTile},
+    pheromone::{PheromoneChannel, PheromoneMap, PheromoneSource, Team},
+    resource::{GrowthRates, ResourceMap, ResourceType},
+    simulation::{Simulation, SimulationRng},
+    team_color::TEAM_COUNT,
+    terrain::{TerrainClass, TerrainMap, TerrainType},
+    unit::{MoveOrder, MovePolicy, TeamId, Unit, UnitId},
+};
+
+/// The first bytes of a savegame file
+pub const SAVEGAME_SIGNATURE: [u8; 4] = *b"G1SV";
+
+/// The version of the savegame format written by `save`, the only one `load` reads
+pub const SAVEGAME_VERSION: u8 = 1;
+
+/// The largest width and height of the map of a savegame, the ones of the game's maps of 64 x 64 blocks
+pub const MAX_MAP_SIZE: usize = 1024;
+
+#[derive(Debug)]
+pub enum SaveLoadError {
+    Io(io::Error),
+    InvalidSignature {
+        signature: [u8; 4],
+    },
+    UnsupportedVersion {
+        version: u8,
+    },
+    /// A value out of its range, for instance an unknown unit kind
+    InvalidValue {
+        offset: usize,
+        what: &'static str,
+    },
+    /// A run of a grid going past its end
+    RunOverflow {
+        offset: usize,
+    },
+}
+impl std::fmt::Display for SaveLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SaveLoadError::*;
+        match self {
+            Io(error) => write!(f, "cannot read savegame: {error}"),
+            InvalidSignature { signature } => {
+                write!(f, "invalid savegame signature {signature:?}")
+            }
+            UnsupportedVersion { version } => {
+                write!(f, "unsupported savegame version {version}")
+            }
+            InvalidValue { offset, what } => write!(f, "invalid {what} at offset {offset}"),
+            RunOverflow { offset } => {
+                write!(f, "run overflowing its grid at offset {offset}")
+            }
+        }
+    }
+}
+impl std::error::Error for SaveLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveLoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+fn building_type_to_bytes(ty: BuildingType) -> [u8; 2] {
+    use BuildingType::*;
+    match ty {
+        Hive => [0, 0],
+        Hospital(level) => [1, level.into()],
+        Inn(level) => [2, level.into()],
+        Tower(level) => [3, level.into()],
+        School(level) => [4, level.into()],
+        Racetrack(level) => [5, level.into()],
+        Dojo(level) => [6, level.into()],
+        Pool(level) => [7, level.into()],
+        Obelisk(level) => [8, level.into()],
+        Wonder(level) => [9, level.into()],
+        ConstructionSite(ty) => [10, ty.into()],
+    }
+}
+
+fn building_type_from_bytes([tag, level]: [u8; 2]) -> Option<BuildingType> {
+    use BuildingType::*;
+    let level = level as usize;
+    let building_level = || {
+        use BuildingLevel::*;
+        [Level0, Level1, Level2, Level3, Level4].get(level).copied()
+    };
+    Some(match tag {
+        0 => Hive,
+        1 => Hospital(building_level()?),
+        2 => Inn(building_level()?),
+        3 => Tower(building_level()?),
+        4 => School(building_level()?),
+        5 => Racetrack(building_level()?),
+        6 => Dojo(building_level()?),
+        7 => Pool(building_level()?),
+        8 => Obelisk(building_level()?),
+        9 => {
+            use WonderLevel::*;
+            let levels = [
+                Level0, Level1, Level2, Level3, Level4, Level5, Level6, Level7,
+            ];
+            Wonder(*levels.get(level)?)
+        }
+        10 => {
+            use ConstructionSiteType::*;
+            let types = [Hive, Size2, Size3, Size4, Size5, Size6, Size8];
+            ConstructionSite(*types.get(level)?)
+        }
+        _ => return None,
+    })
+}
+
+fn move_order_to_u8(order: MoveOrder) -> u8 {
+    match order {
+        MoveOrder::Idle => 0,
+        MoveOrder::Walk => 1,
+        MoveOrder::Swim => 2,
+        MoveOrder::Fly => 3,
+    }
+}
+
+fn move_order_from_u8(order: u8) -> Option<MoveOrder> {
+    [
+        MoveOrder::Idle,
+        MoveOrder::Walk,
+        MoveOrder::Swim,
+        MoveOrder::Fly,
+    ]
+    .get(order as usize)
+    .copied()
+}
+
+/// Whether a rect of at least one tile lies within a map of the given size
+fn is_within(rect: Rect, width: usize, height: usize) -> bool {
+    let within = |start: i16, size: i16, len: usize| {
+        start >= 0 && size > 0 && start as usize + size as usize <= len
+    };
+    within(rect.top_left.x, rect.size.x, width) && within(rect.top_left.y, rect.size.y, height)
+}
+
+/// Accumulates the bytes of a savegame, integers being little endian
+#[derive(Default)]
+struct SaveWriter {
+    bytes: Vec<u8>,
+}
+impl SaveWriter {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+    fn coord(&mut self, coord: Coord) {
+        self.bytes.extend(coord.x.to_le_bytes());
+        self.bytes.extend(coord.y.to_le_bytes());
+    }
+    /// Write the values as runs of a count followed by a value, as most grids have large uniform areas
+    fn runs<T: Copy + PartialEq>(&mut self, grid: &Grid<T>, write: impl Fn(&mut Self, T)) {
+        let mut values = grid.iter().copied().peekable();
+        while let Some(value) = values.next() {
+            let mut count = 1;
+            while values.next_if_eq(&value).is_some() {
+                count += 1;
+            }
+            self.u32(count);
+            write(self, value);
+        }
+    }
+}
+
+/// Reads the bytes of a savegame, keeping track of the offset for error reporting
+struct SaveReader<R: Read> {
+    input: BufReader<R>,
+    offset: usize,
+}
+impl<R: Read> SaveReader<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], SaveLoadError> {
+        let mut bytes = [0; N];
+        self.input
+            .read_exact(&mut bytes)
+            .map_err(SaveLoadError::Io)?;
+        self.offset += N;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, SaveLoadError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+    fn u16(&mut self) -> Result<u16, SaveLoadError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+    fn u32(&mut self) -> Result<u32, SaveLoadError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+    fn u64(&mut self) -> Result<u64, SaveLoadError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+    fn coord(&mut self) -> Result<Coord, SaveLoadError> {
+        let x = i16::from_le_bytes(self.bytes()?);
+        let y = i16::from_le_bytes(self.bytes()?);
+        Ok(Coord::new(x, y))
+    }
+    /// Returns an error for the value of the given size just read
+    fn invalid<T>(&self, size: usize, what: &'static str) -> Result<T, SaveLoadError> {
+        Err(SaveLoadError::InvalidValue {
+            offset: self.offset - size,
+            what,
+        })
+    }
+    /// Read the runs of the intensities of a pheromone map
+    fn pheromone_map(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> Result<PheromoneMap, SaveLoadError> {
+        let mut intensities = Grid::new(width, height, 0);
+        self.runs(&mut intensities, Self::u16)?;
+        Ok(PheromoneMap::from_intensities(intensities))
+    }
+    /// Read the runs written by `SaveWriter::runs`, filling the grid
+    fn runs<T: Copy>(
+        &mut self,
+        grid: &mut Grid<T>,
+        read: impl Fn(&mut Self) -> Result<T, SaveLoadError>,
+    ) -> Result<(), SaveLoadError> {
+        let mut slots = grid.iter_mut().peekable();
+        while slots.peek().is_some() {
+            let offset = self.offset;
+            let count = self.u32()? as usize;
+            let value = read(self)?;
+            if count == 0 {
+                return Err(SaveLoadError::RunOverflow { offset });
+            }
+            for _ in 0..count {
+                *slots.next().ok_or(SaveLoadError::RunOverflow { offset })? = value;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Save the full state of a simulation, from which it continues identically once loaded
+pub fn save(simulation: &Simulation, mut output: impl Write) -> io::Result<()> {
+    let mut writer = SaveWriter::default();
+    writer.bytes.extend(SAVEGAME_SIGNATURE);
+    writer.u8(SAVEGAME_VERSION);
+    writer.u64(simulation.tick);
+    let rng = &simulation.rng;
+    writer.bytes.extend(rng.get_seed());
+    writer.u64(rng.get_stream());
+    writer.bytes.extend(rng.get_word_pos().to_le_bytes());
+    writer.u64(simulation.growth_rates.regrow.to_bits());
+    writer.u64(simulation.growth_rates.spread.to_bits());
+
+    // grids
+    let terrain = &simulation.terrain;
+    writer.u32(terrain.width() as u32);
+    writer.u32(terrain.height() as u32);
+    writer.runs(&terrain.tiles, SaveWriter::u8);
+    writer.runs(&terrain.classes, |writer, class| {
+        writer.u8(class.map_or(u8::MAX, |class| class.0))
+    });
+    writer.runs(&terrain.types, |writer, ty| writer.u8(ty.into()));
+    writer.runs(&simulation.resources.0, SaveWriter::u8);
+
+    // units and buildings, before the over-map referencing them
+    writer.u32(simulation.units.len() as u32);
+    for unit in &simulation.units {
+        writer.coord(unit.position);
+        writer.u8(unit.step);
+        writer.u8(unit.direction.into());
+        writer.u8(move_order_to_u8(unit.order));
+        writer.u8(unit.speed);
+        writer.u8(UnitKind::all().position(|kind| kind == unit.kind).unwrap() as u8);
+        writer.u8(unit.team.0);
+        match unit.policy {
+            MovePolicy::RandomWalk => writer.u8(0),
+            MovePolicy::Climb(channel) => {
+                writer.u8(1);
+                writer.u8(channel.index() as u8);
+            }
+            MovePolicy::Descend(channel) => {
+                writer.u8(2);
+                writer.u8(channel.index() as u8);
+            }
+        }
+        writer.u8(unit.carried.map_or(u8::MAX, u8::from));
+    }
+    writer.u32(simulation.buildings.len() as u32);
+    for building in &simulation.buildings {
+        writer.bytes.extend(building_type_to_bytes(building.ty));
+        writer.coord(building.position);
+        writer.coord(building.size);
+        writer.u8(building.team.0);
+    }
+    writer.runs(&simulation.over_map.0, |writer, tile| match tile {
+        OverMapTile::Empty => writer.u8(0),
+        OverMapTile::Unit(id) => {
+            writer.u8(1);
+            writer.u32(id.0);
+        }
+        OverMapTile::Building(id) => {
+            writer.u8(2);
+            writer.u32(id.0);
+        }
+    });
+
+    // pheromones of resources, then of each team
+    for map in &simulation.gather_maps {
+        writer.runs(map.intensities(), SaveWriter::u16);
+    }
+    writer.u32(simulation.teams.len() as u32);
+    for team in &simulation.teams {
+        for map in &team.pheromone_maps {
+            writer.runs(map.intensities(), SaveWriter::u16);
+        }
+        writer.u32(team.sources.len() as u32);
+        for source in &team.sources {
+            writer.u8(source.channel.index() as u8);
+            writer.coord(source.area.top_left);
+            writer.coord(source.area.size);
+            writer.u16(source.amount);
+        }
+    }
+    output.write_all(&writer.bytes)
+}
+
+/// Load a savegame written by `save`
+pub fn load(input: impl Read) -> Result<Simulation, SaveLoadError> {
+    let mut reader = SaveReader {
+        input: BufReader::new(input),
+        offset: 0,
+    };
+    let signature = reader.bytes()?;
+    if signature != SAVEGAME_SIGNATURE {
+        return Err(SaveLoadError::InvalidSignature { signature });
+    }
+    let version = reader.u8()?;
+    if version != SAVEGAME_VERSION {
+        return Err(SaveLoadError::UnsupportedVersion { version });
+    }
+    let tick = reader.u64()?;
+    let mut rng = SimulationRng::from_seed(reader.bytes()?);
+    rng.set_stream(reader.u64()?);
+    rng.set_word_pos(u128::from_le_bytes(reader.bytes()?));
+    let growth_rates = GrowthRates {
+        regrow: f64::from_bits(reader.u64()?),
+        spread: f64::from_bits(reader.u64()?),
+    };
+
+    // grids
+    let width = reader.u32()? as usize;
+    let height = reader.u32()? as usize;
+    // before allocating the grids
+    if width > MAX_MAP_SIZE || height > MAX_MAP_SIZE {
+        return reader.invalid(8, "map size");
+    }
+    let mut terrain = TerrainMap::new(width, height);
+    reader.runs(&mut terrain.tiles, SaveReader::u8)?;
+    reader.runs(&mut terrain.classes, |reader| match reader.u8()? {
+        u8::MAX => Ok(None),
+        class if TerrainClass(class).is_valid() => Ok(Some(TerrainClass(class))),
+        _ => reader.invalid(1, "terrain class"),
+    })?;
+    reader.runs(&mut terrain.types, |reader| {
+        match TerrainType::try_from(reader.u8()?) {
+            Ok(ty) => Ok(ty),
+            Err(_) => reader.invalid(1, "terrain type"),
+        }
+    })?;
+    let mut resources = ResourceMap(Grid::new(width, height, 0));
+    reader.runs(&mut resources.0, SaveReader::u8)?;
+
+    // units and buildings, each on free tiles within the map, which gives the expected over-map
+    let mut expected_over_map = OverMap::new(width, height);
+    let mut occupy = |rect: Rect, tile: OverMapTile| {
+        let free = is_within(rect, width, height)
+            && expected_over_map.rect_has_value(rect, OverMapTile::Empty);
+        if free {
+            expected_over_map.set_rect_value(rect, tile);
+        }
+        free
+    };
+    let unit_count = reader.u32()? as usize;
+    let mut units = Vec::new();
+    for id in 0..unit_count {
+        let offset = reader.offset;
+        let position = reader.coord()?;
+        let step = reader.u8()?;
+        let direction = match Direction::try_from(reader.u8()?) {
+            Ok(direction) => direction,
+            Err(_) => return reader.invalid(1, "direction"),
+        };
+        let order = match move_order_from_u8(reader.u8()?) {
+            Some(order) => order,
+            None => return reader.invalid(1, "move order"),
+        };
+        let speed = reader.u8()?;
+        let kind = match UnitKind::all().nth(reader.u8()? as usize) {
+            Some(kind) => kind,
+            None => return reader.invalid(1, "unit kind"),
+        };
+        let team = TeamId(reader.u8()?);
+        let policy = match reader.u8()? {
+            0 => MovePolicy::RandomWalk,
+            tag @ (1 | 2) => {
+                let channel = match PheromoneChannel::from_index(reader.u8()? as usize) {
+                    Some(channel) => channel,
+                    None => return reader.invalid(1, "pheromone channel"),
+                };
+                if tag == 1 {
+                    MovePolicy::Climb(channel)
+                } else {
+                    MovePolicy::Descend(channel)
+                }
+            }
+            _ => return reader.invalid(1, "move policy"),
+        };
+        let carried = match reader.u8()? {
+            u8::MAX => None,
+            resource => match ResourceType::try_from(resource) {
+                Ok(resource) => Some(resource),
+                Err(_) => return reader.invalid(1, "carried resource"),
+            },
+        };
+        let tile = OverMapTile::Unit(UnitId(id as u32));
+        let mut occupied = occupy(Rect::new(position, Coord::new(1, 1)), tile);
+        if order != MoveOrder::Idle {
+            let next_position = position + direction.delta();
+            occupied = occupied && occupy(Rect::new(next_position, Coord::new(1, 1)), tile);
+        }
+        if !occupied || team.0 as usize >= TEAM_COUNT {
+            return Err(SaveLoadError::InvalidValue {
+                offset,
+                what: "unit",
+            });
+        }
+        units.push(Unit {
+            position,
+            step,
+            direction,
+            order,
+            speed,
+            kind,
+            team,
+            policy,
+            carried,
+        });
+    }
+    let building_count = reader.u32()? as usize;
+    let mut buildings = Vec::new();
+    for id in 0..building_count {
+        let offset = reader.offset;
+        let ty = match building_type_from_bytes(reader.bytes()?) {
+            Some(ty) => ty,
+            None => return reader.invalid(2, "building type"),
+        };
+        let building = Building {
+            ty,
+            position: reader.coord()?,
+            size: reader.coord()?,
+            team: TeamId(reader.u8()?),
+        };
+        let tile = OverMapTile::Building(BuildingId(id as u32));
+        if !occupy(building.footprint(), tile) || building.team.0 as usize >= TEAM_COUNT {
+            return Err(SaveLoadError::InvalidValue {
+                offset,
+                what: "building",
+            });
+        }
+        buildings.push(building);
+    }
+    let offset = reader.offset;
+    let mut over_map = OverMap::new(width, height);
+    reader.runs(&mut over_map.0, |reader| match reader.u8()? {
+        0 => Ok(OverMapTile::Empty),
+        1 => match reader.u32()? {
+            id if (id as usize) < unit_count => Ok(OverMapTile::Unit(UnitId(id))),
+            _ => reader.invalid(4, "unit id"),
+        },
+        2 => match reader.u32()? {
+            id if (id as usize) < building_count => Ok(OverMapTile::Building(BuildingId(id))),
+            _ => reader.invalid(4, "building id"),
+        },
+        _ => reader.invalid(1, "over-map tile"),
+    })?;
+    if over_map != expected_over_map {
+        return Err(SaveLoadError::InvalidValue {
+            offset,
+            what: "over-map",
+        });
+    }
+
+    // pheromones of resources, then of each team
+    let mut gather_maps = [(); 4].map(|_| PheromoneMap::new(width, height));
+    for map in &mut gather_maps {
+        *map = reader.pheromone_map(width, height)?;
+    }
+    let team_count = reader.u32()? as usize;
+    if team_count > TEAM_COUNT {
+        return reader.invalid(4, "team count");
+    }
+    let mut teams = Vec::new();
+    for _ in 0..team_count {
+        let mut team = Team::new(width, height);
+        for map in &mut team.pheromone_maps {
+            *map = reader.pheromone_map(width, height)?;
+        }
+        let source_count = reader.u32()?;
+        for _ in 0..source_count {
+            let channel = match PheromoneChannel::from_index(reader.u8()? as usize) {
+                Some(channel) => channel,
+                None => return reader.invalid(1, "pheromone channel"),
+            };
+            let area = Rect::new(reader.coord()?, reader.coord()?);
+            // the area may go past the map, but its tiles must be countable
+            let countable = |start: i16, size: i16| size >= 0 && start.checked_add(size).is_some();
+            if !countable(area.top_left.x, area.size.x) || !countable(area.top_left.y, area.size.y)
+            {
+                return reader.invalid(8, "pheromone source area");
+            }
+            team.sources.push(PheromoneSource {
+                channel,
+                area,
+                amount: reader.u16()?,
+            });
+        }
+        teams.push(team);
+    }
+    let has_team = |team: TeamId| (team.0 as usize) < team_count;
+    if !units.iter().all(|unit| has_team(unit.team))
+        || !buildings.iter().all(|building| has_team(building.team))
+    {
+        return Err(SaveLoadError::InvalidValue {
+            offset: reader.offset,
+            what: "team count",
+        });
+    }
+
+    let mut simulation = Simulation::with_rng(terrain, rng);
+    simulation.resources = resources;
+    simulation.over_map = over_map;
+    simulation.units = units;
+    simulation.buildings = buildings;
+    simulation.teams = teams;
+    simulation.gather_maps = gather_maps;
+    simulation.growth_rates = growth_rates;
+    simulation.tick = tick;
+    Ok(simulation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legacy::{resource::ResourceType, terrain::TerrainType};
+
+    #[test]
+    fn save_load_continue() {
+        let mut rng = SimulationRng::seed_from_u64(0);
+        let mut terrain = TerrainMap::new(48, 32);
+        for y in 4..28 {
+            for x in 4..44 {
+                terrain.set_type(Coord::new(x, y), TerrainType::Grass, &mut rng);
+            }
+        }
+        terrain.set_resource(Coord::new(30, 20), ResourceType::Wheat, &mut rng);
+        let mut simulation = Simulation::with_rng(terrain, rng);
+        for (i, kind) in UnitKind::all().enumerate() {
+            let position = Coord::new(10 + 4 * i as i16, 10);
+            simulation.try_spawn_unit(position, kind, TeamId(i as u8));
+        }
+        let ty = BuildingType::Inn(BuildingLevel::Level2);
+        simulation.try_spawn_building(Coord::new(20, 20), ty, 2, TeamId(1));
+        simulation.teams[2].pheromone_maps[5].set(Coord::new(7, 8), 300);
+        let channel = PheromoneChannel::from_index(2).unwrap();
+        let source = PheromoneSource::point(channel, Coord::new(40, 6), 8);
+        simulation.teams[0].sources.push(source);
+        simulation.units[2].policy = MovePolicy::Climb(PheromoneChannel::from_index(5).unwrap());
+        for _ in 0..100 {
+            simulation.step();
+        }
+
+        let mut bytes = Vec::new();
+        save(&simulation, &mut bytes).unwrap();
+        let mut loaded = load(bytes.as_slice()).unwrap();
+        assert_eq!(loaded, simulation);
+        for _ in 0..200 {
+            simulation.step();
+            loaded.step();
+        }
+        assert_eq!(loaded, simulation);
+
+        assert!(matches!(
+            load(&bytes[..bytes.len() - 1]),
+            Err(SaveLoadError::Io(_))
+        ));
+        bytes[4] = SAVEGAME_VERSION + 1;
+        assert!(matches!(
+            load(bytes.as_slice()),
+            Err(SaveLoadError::UnsupportedVersion { .. })
+        ));
+        bytes[0] = b'X';
+        assert!(matches!(
+            load(bytes.as_slice()),
+            Err(SaveLoadError::InvalidSignature { .. })
+        ));
+    }
+
+    #[test]
+    fn load_invalid() {
+        let mut simulation = Simulation::new(TerrainMap::new(16, 16), 0);
+        simulation.try_spawn_unit(Coord::new(2, 3), UnitKind::Worker, TeamId(0));
+        let ty = BuildingType::Hive;
+        simulation.try_spawn_building(Coord::new(8, 8), ty, 3, TeamId(1));
+        let save_load = |modify: fn(&mut Simulation)| {
+            let mut modified = simulation.clone();
+            modify(&mut modified);
+            let mut bytes = Vec::new();
+            save(&modified, &mut bytes).unwrap();
+            load(bytes.as_slice())
+        };
+        let invalid = |modify, expected| match save_load(modify) {
+            Err(SaveLoadError::InvalidValue { what, .. }) => assert_eq!(what, expected),
+            result => panic!("expected invalid {expected}, got {result:?}"),
+        };
+        // all classes with tiles are accepted, as when loading maps
+        save_load(|s| {
+            s.terrain.set_class(
+                Coord::new(0, 0),
+                TerrainClass(TerrainClass::COUNT - 1),
+                &mut s.rng,
+            )
+        })
+        .unwrap();
+        invalid(
+            |s| {
+                s.terrain
+                    .classes
+                    .set(Coord::new(0, 0), Some(TerrainClass(TerrainClass::COUNT)))
+            },
+            "terrain class",
+        );
+        invalid(|s| s.units[0].position = Coord::new(16, 3), "unit");
+        invalid(|s| s.units[0].team = TeamId(TEAM_COUNT as u8), "unit");
+        invalid(|s| s.buildings[0].size = Coord::new(9, 9), "building");
+        invalid(
+            |s| s.buildings[0].team = TeamId(TEAM_COUNT as u8),
+            "building",
+        );
+        invalid(
+            |s| {
+                s.over_map
+                    .set(Coord::new(0, 0), OverMapTile::Unit(UnitId(0)))
+            },
+            "over-map",
+        );
+        invalid(|s| s.teams.truncate(1), "team count");
+        invalid(
+            |s| *s = Simulation::new(TerrainMap::new(MAX_MAP_SIZE + 1, 1), 0),
+            "map size",
+        );
+    }
+}
@@ -0,0 +1,588 @@
+use std::io::Read;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use super::{
+    building::{Building, BuildingId, BuildingType},
+    direction::Direction,
+    grid::{Coord, Grid2D, Rect},
+    mobility::UnitKind,
+    over_map::{OverMap, OverMapTile},
+    pheromone::{
+        DiffusionProportion, GatherMaps, PheromoneChannel, PheromoneKind, PheromoneMap, Team,
+    },
+    resource::{GrowthRates, ResourceMap, ResourceType},
+    stored_map::{self, MapLoadError, StoredMap},
+    team_color::TEAM_COUNT,
+    terrain::TerrainMap,
+    unit::{move_unit, MovePolicy, TeamId, Unit, UnitId},
+};
+
+/// Number of ticks between two growths of resources
+pub const GROWTH_PERIOD: u64 = 32;
+
+/// Number of ticks between two evaporations and diffusions of a pheromone map.
+/// The maps of each channel are dispersed at a different tick of the period, the one given by the index of the channel.
+pub const DISPERSION_PERIOD: u64 = 8;
+
+/// Proportion of pheromone exchanged between neighbouring tiles at each dispersion
+pub const DIFFUSION_PERCENT: i32 = 25;
+
+/// Pheromone deposited per tick on the passable tiles around resource tiles, in the shared gather map of their resource.
+/// It is deposited in a batch every `DISPERSION_PERIOD` ticks.
+pub const RESOURCE_EMISSION: u16 = 16;
+
+/// Pheromone deposited at every tick on hives, in the collect maps of their team
+pub const HIVE_EMISSION: u16 = 16;
+
+/// The random number generator of the simulation, portable so that a seed gives the same game everywhere
+pub type SimulationRng = ChaCha8Rng;
+
+/// An order given by a player, applied at the start of a tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    SpawnUnit {
+        position: Coord,
+        kind: UnitKind,
+        team: TeamId,
+    },
+    /// Change how a unit chooses where to move
+    SetPolicy { unit: UnitId, policy: MovePolicy },
+}
+
+/// The state of a game, advanced one tick at a time by `step`, independently of any rendering
+#[derive(Clone, Debug, PartialEq)]
+pub struct Simulation {
+    pub terrain: TerrainMap,
+    pub resources: ResourceMap,
+    pub over_map: OverMap,
+    pub units: Vec<Unit>,
+    pub buildings: Vec<Building>,
+    /// The teams owning units, created with the first unit of their team
+    pub teams: Vec<Team>,
+    /// The pheromone of resources, which units of all teams follow with the gather maps of their team
+    pub gather_maps: GatherMaps,
+    pub growth_rates: GrowthRates,
+    pub(crate) tick: u64,
+    pub(crate) rng: SimulationRng,
+}
+impl Simulation {
+    /// Create a simulation without units nor buildings, with fully grown resources
+    pub fn new(terrain: TerrainMap, seed: u64) -> Self {
+        Self::with_rng(terrain, SimulationRng::seed_from_u64(seed))
+    }
+    /// Create a simulation without units nor buildings, continuing the sequence of rng
+    pub fn with_rng(terrain: TerrainMap, rng: SimulationRng) -> Self {
+        let (width, height) = (terrain.width(), terrain.height());
+        let over_map = OverMap::new(width, height);
+        Self {
+            resources: ResourceMap::new(&terrain),
+            terrain,
+            over_map,
+            units: Vec::new(),
+            buildings: Vec::new(),
+            teams: Vec::new(),
+            gather_maps: [(); 4].map(|_| PheromoneMap::new(width, height)),
+            growth_rates: GrowthRates::default(),
+            tick: 0,
+            rng,
+        }
+    }
+    /// Create a simulation with a worker at each queen position of the map, each in its own team
+    pub fn from_stored_map(map: &StoredMap, rng: SimulationRng) -> Self {
+        let mut simulation = Self::with_rng(map.terrain.clone(), rng);
+        for (team, &position) in map.queen_positions.iter().enumerate() {
+            simulation.try_spawn_unit(position, UnitKind::Worker, TeamId(team as u8));
+        }
+        simulation
+    }
+    /// Decode a map and create its simulation, all randomness coming from the seed
+    pub fn load(input: impl Read, seed: u64) -> Result<Self, MapLoadError> {
+        let mut rng = SimulationRng::seed_from_u64(seed);
+        let map = stored_map::load(input, &mut rng)?;
+        Ok(Self::from_stored_map(&map, rng))
+    }
+    /// The number of steps done so far
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn unit(&self, id: UnitId) -> &Unit {
+        &self.units[id.0 as usize]
+    }
+    pub fn building(&self, id: BuildingId) -> &Building {
+        &self.buildings[id.0 as usize]
+    }
+    /// Make sure the team exists, as well as the ones before it
+    fn add_teams_up_to(&mut self, team: TeamId) {
+        let (width, height) = (self.terrain.width(), self.terrain.height());
+        while self.teams.len() <= team.0 as usize {
+            self.teams.push(Team::new(width, height));
+        }
+    }
+    /// Spawn a unit if the position is free and in bounds, and the team one of the `TEAM_COUNT` ones
+    pub fn try_spawn_unit(
+        &mut self,
+        position: Coord,
+        kind: UnitKind,
+        team: TeamId,
+    ) -> Option<UnitId> {
+        if team.0 as usize >= TEAM_COUNT
+            || !self.over_map.is_in_bounds(position)
+            || self.over_map.get(position) != OverMapTile::Empty
+        {
+            return None;
+        }
+        self.add_teams_up_to(team);
+        let id = UnitId(self.units.len() as u32);
+        self.units.push(Unit::new(position, kind, team));
+        self.over_map.set(position, OverMapTile::Unit(id));
+        Some(id)
+    }
+    /// Spawn a square building of the given side length if the area is free and in bounds,
+    /// and the team one of the `TEAM_COUNT` ones
+    pub fn try_spawn_building(
+        &mut self,
+        position: Coord,
+        ty: BuildingType,
+        side_len: i16,
+        team: TeamId,
+    ) -> Option<BuildingId> {
+        let size = Coord::new(side_len, side_len);
+        let rect = Rect::new(position, size);
+        let bottom_right = position + size - Coord::new(1, 1);
+        if team.0 as usize >= TEAM_COUNT
+            || !self.over_map.is_in_bounds(position)
+            || !self.over_map.is_in_bounds(bottom_right)
+            || !self.over_map.rect_has_value(rect, OverMapTile::Empty)
+        {
+            return None;
+        }
+        self.add_teams_up_to(team);
+        let id = BuildingId(self.buildings.len() as u32);
+        self.buildings.push(Building {
+            ty,
+            position,
+            size,
+            team,
+        });
+        self.over_map
+            .set_rect_value(rect, OverMapTile::Building(id));
+        Some(id)
+    }
+    /// Execute a player command, which does nothing if it is not possible
+    pub fn apply(&mut self, command: &Command) {
+        match *command {
+            Command::SpawnUnit {
+                position,
+                kind,
+                team,
+            } => {
+                self.try_spawn_unit(position, kind, team);
+            }
+            Command::SetPolicy { unit, policy } => {
+                if let Some(unit) = self.units.get_mut(unit.0 as usize) {
+                    unit.policy = policy;
+                }
+            }
+        }
+    }
+    /// Deposit the pheromone of sources, resource tiles and hives, and periodically disperse it
+    fn update_pheromones(&mut self) {
+        for team in &mut self.teams {
+            team.emit();
+        }
+        for building in &self.buildings {
+            if building.ty == BuildingType::Hive {
+                let team = &mut self.teams[building.team.0 as usize];
+                for resource in ResourceType::all() {
+                    let channel = PheromoneChannel {
+                        resource,
+                        kind: PheromoneKind::Collect,
+                    };
+                    team.pheromone_map_mut(channel)
+                        .deposit_footprint(building, HIVE_EMISSION);
+                }
+            }
+        }
+        if self.tick % DISPERSION_PERIOD == DISPERSION_PERIOD - 1 {
+            // resource tiles deposit the pheromone of the whole period at once, sparing a scan of the map at every tick.
+            // As they cannot be entered, nor diffused through, they deposit it on the passable tiles around them.
+            let terrain = &self.terrain;
+            let width = self.resources.width();
+            for (index, &amount) in self.resources.0.iter().enumerate() {
+                if amount == 0 {
+                    continue;
+                }
+                let position = Coord::new((index % width) as i16, (index / width) as i16);
+                if let Some(resource) = terrain.resource_type(position) {
+                    let map = &mut self.gather_maps[u8::from(resource) as usize];
+                    let neighbours = Direction::all()
+                        .map(|direction| position + direction.delta())
+                        .filter(|&neighbour| {
+                            terrain.is_in_bounds(neighbour) && terrain.passable(neighbour)
+                        });
+                    for neighbour in neighbours {
+                        map.deposit(neighbour, RESOURCE_EMISSION * DISPERSION_PERIOD as u16);
+                    }
+                }
+            }
+        }
+        let terrain = &self.terrain;
+        let passable = |position| terrain.passable(position);
+        let proportion = DiffusionProportion::from_percent(DIFFUSION_PERCENT);
+        let phase = self.tick % DISPERSION_PERIOD;
+        for channel in PheromoneChannel::all() {
+            if channel.index() as u64 % DISPERSION_PERIOD != phase {
+                continue;
+            }
+            if channel.kind == PheromoneKind::Gather {
+                self.gather_maps[u8::from(channel.resource) as usize]
+                    .disperse(&proportion, passable);
+            }
+            for team in &mut self.teams {
+                team.pheromone_map_mut(channel)
+                    .disperse(&proportion, passable);
+            }
+        }
+    }
+    /// Advance the game by one tick
+    pub fn step(&mut self) {
+        for (index, unit) in self.units.iter_mut().enumerate() {
+            let id = UnitId(index as u32);
+            let team = &mut self.teams[unit.team.0 as usize];
+            let order_ended = move_unit(
+                id,
+                unit,
+                &self.terrain,
+                &mut self.over_map,
+                team,
+                &self.gather_maps,
+                &mut self.rng,
+            );
+            if order_ended && unit.kind == UnitKind::Worker {
+                work(
+                    unit,
+                    &mut self.terrain,
+                    &mut self.resources,
+                    &self.over_map,
+                    &self.buildings,
+                    &mut self.rng,
+                );
+            }
+        }
+        self.update_pheromones();
+        if self.tick % GROWTH_PERIOD == GROWTH_PERIOD - 1 {
+            self.resources
+                .grow(&mut self.terrain, &self.growth_rates, &mut self.rng);
+        }
+        self.tick += 1;
+    }
+}
+
+/// Let a worker carrying nothing harvest a resource tile next to it,
+/// and a worker carrying a resource deliver it to a hive of its team next to it
+fn work(
+    unit: &mut Unit,
+    terrain: &mut TerrainMap,
+    resources: &mut ResourceMap,
+    over_map: &OverMap,
+    buildings: &[Building],
+    rng: &mut SimulationRng,
+) {
+    let mut neighbours = Direction::all()
+        .map(|direction| unit.position + direction.delta())
+        .filter(|&neighbour| over_map.is_in_bounds(neighbour));
+    match unit.carried {
+        None => {
+            unit.carried =
+                neighbours.find_map(|neighbour| resources.harvest(terrain, neighbour, rng));
+        }
+        Some(_) => {
+            let is_hive = |tile| match tile {
+                OverMapTile::Building(id) => {
+                    let building = &buildings[id.0 as usize];
+                    building.ty == BuildingType::Hive && building.team == unit.team
+                }
+                _ => false,
+            };
+            if neighbours.any(|neighbour| is_hive(over_map.get(neighbour))) {
+                unit.carried = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::legacy::{grid::Grid, pheromone::PheromoneSource, terrain::TerrainType};
+
+    #[test]
+    fn units_move_on_land() {
+        let mut terrain = TerrainMap::new(16, 16);
+        for y in 4..12 {
+            for x in 4..12 {
+                terrain.set(Coord::new(x, y), TerrainType::Sand);
+            }
+        }
+        let mut simulation = Simulation::new(terrain, 0);
+        let worker = simulation
+            .try_spawn_unit(Coord::new(6, 6), UnitKind::Worker, TeamId(0))
+            .unwrap();
+        let warrior = simulation
+            .try_spawn_unit(Coord::new(8, 8), UnitKind::Warrior, TeamId(1))
+            .unwrap();
+        assert_eq!(
+            simulation.try_spawn_unit(Coord::new(8, 8), UnitKind::Worker, TeamId(0)),
+            None
+        );
+        let mut worker_moved = false;
+        for _ in 0..2000 {
+            simulation.step();
+            worker_moved |= simulation.unit(worker).position != Coord::new(6, 6);
+            let position = simulation.unit(warrior).position;
+            assert_ne!(simulation.terrain.get(position), TerrainType::Water);
+            assert_eq!(
+                simulation.over_map.get(position),
+                OverMapTile::Unit(warrior)
+            );
+        }
+        assert_eq!(simulation.tick(), 2000);
+        assert!(worker_moved);
+    }
+
+    #[test]
+    fn workers_harvest_and_deliver() {
+        let rng = &mut SimulationRng::seed_from_u64(0);
+        let mut terrain = TerrainMap::new(16, 16);
+        terrain.set_rect_value(
+            Rect::new(Coord::new(0, 0), Coord::new(16, 16)),
+            TerrainType::Grass,
+        );
+        let wheat = Coord::new(8, 8);
+        terrain.set_resource(wheat, ResourceType::Wheat, rng);
+        let mut simulation = Simulation::new(terrain, 0);
+        simulation
+            .try_spawn_building(Coord::new(2, 2), BuildingType::Hive, 2, TeamId(0))
+            .unwrap();
+        let harvester = simulation
+            .try_spawn_unit(Coord::new(7, 8), UnitKind::Worker, TeamId(0))
+            .unwrap();
+        let deliverer = simulation
+            .try_spawn_unit(Coord::new(4, 3), UnitKind::Worker, TeamId(0))
+            .unwrap();
+        simulation.units[deliverer.0 as usize].carried = Some(ResourceType::Wood);
+        // both end their first, idle, order where they spawned
+        for _ in 0..100 {
+            simulation.step();
+        }
+        assert_eq!(
+            simulation.unit(harvester).carried,
+            Some(ResourceType::Wheat)
+        );
+        assert_eq!(simulation.resources.get(wheat), 7);
+        assert_eq!(simulation.unit(deliverer).carried, None);
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        ignore = "timings are only meaningful with optimizations"
+    )]
+    fn tick_cost_on_large_map() {
+        let rng = &mut SimulationRng::seed_from_u64(0);
+        let types = Grid::new(1024, 1024, TerrainType::Grass);
+        let mut terrain = TerrainMap::from_types(&types, rng);
+        for y in (8..1024).step_by(16) {
+            for x in (8..1024).step_by(16) {
+                let resource = ResourceType::all().nth((x + y) as usize / 16 % 4).unwrap();
+                terrain.set_resource(Coord::new(x, y), resource, rng);
+            }
+        }
+        let mut simulation = Simulation::new(terrain, 0);
+        for team in 0..TEAM_COUNT as u8 {
+            let hive = Coord::new(64 + 112 * team as i16, 500);
+            simulation
+                .try_spawn_building(hive, BuildingType::Hive, 2, TeamId(team))
+                .unwrap();
+            for i in 0..16 {
+                let position = hive + Coord::new(i % 4 * 2, 4 + i / 4 * 2);
+                let id = simulation
+                    .try_spawn_unit(position, UnitKind::Worker, TeamId(team))
+                    .unwrap();
+                let resource = ResourceType::all().nth(i as usize % 4).unwrap();
+                let kind = PheromoneKind::Gather;
+                simulation.units[id.0 as usize].policy =
+                    MovePolicy::Climb(PheromoneChannel { resource, kind });
+            }
+        }
+        let mut worst = Duration::ZERO;
+        let start = Instant::now();
+        let ticks = 4 * GROWTH_PERIOD;
+        for _ in 0..ticks {
+            let tick_start = Instant::now();
+            simulation.step();
+            worst = worst.max(tick_start.elapsed());
+        }
+        let average = start.elapsed() / ticks as u32;
+        // the timestep of the viewer being 30 ms
+        assert!(
+            average < Duration::from_millis(10),
+            "average tick {average:?}"
+        );
+        assert!(worst < Duration::from_millis(30), "worst tick {worst:?}");
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let rng = &mut SimulationRng::seed_from_u64(0);
+        let mut terrain = TerrainMap::new(1024, 1024);
+        for y in 0..32 {
+            for x in 0..32 {
+                let ty = if (x / 8 + y / 8) % 2 == 0 {
+                    TerrainType::Sand
+                } else {
+                    TerrainType::Grass
+                };
+                terrain.set_type(Coord::new(x, y), ty, rng);
+            }
+        }
+        let map = StoredMap {
+            header: None,
+            terrain,
+            queen_positions: (0..4).map(|i| Coord::new(4 + 6 * i, 10)).collect(),
+            view_position: Coord::new(0, 0),
+        };
+        let mut bytes = Vec::new();
+        stored_map::save(&map, &mut bytes).unwrap();
+        let run = |seed| {
+            let mut simulation = Simulation::load(bytes.as_slice(), seed).unwrap();
+            for _ in 0..200 {
+                simulation.step();
+            }
+            simulation
+        };
+        let (first, second) = (run(7), run(7));
+        assert_eq!(first.units.len(), 4);
+        assert_eq!(first.units, second.units);
+        assert_eq!(first.terrain.tiles, second.terrain.tiles);
+    }
+
+    #[test]
+    fn pheromone_fields() {
+        let rng = &mut SimulationRng::seed_from_u64(0);
+        let mut terrain = TerrainMap::new(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                terrain.set_type(Coord::new(x, y), TerrainType::Grass, rng);
+            }
+        }
+        let wheat = Coord::new(24, 24);
+        terrain.set_resource(wheat, ResourceType::Wheat, rng);
+        // a wall of stone, not crossed by pheromone
+        for y in 0..32 {
+            terrain.set_resource(Coord::new(28, y), ResourceType::Stone, rng);
+        }
+        let mut simulation = Simulation::new(terrain, 0);
+        simulation
+            .try_spawn_building(Coord::new(4, 4), BuildingType::Hive, 2, TeamId(0))
+            .unwrap();
+        let channel = |resource, kind| PheromoneChannel { resource, kind };
+        let stone = channel(ResourceType::Stone, PheromoneKind::Gather);
+        let source = PheromoneSource::point(stone, Coord::new(16, 2), 4);
+        simulation.teams[0].sources.push(source);
+        for _ in 0..400 {
+            simulation.step();
+        }
+
+        // fields decrease away from their sources
+        let team = &simulation.teams[0];
+        let intensity =
+            |channel, x, y| team.intensity(&simulation.gather_maps, channel, Coord::new(x, y));
+        let collect = channel(ResourceType::Wheat, PheromoneKind::Collect);
+        assert!(intensity(collect, 5, 5) > intensity(collect, 8, 5));
+        assert!(intensity(collect, 8, 5) > intensity(collect, 11, 5));
+        assert!(intensity(collect, 11, 5) > 0);
+        assert_eq!(intensity(collect, 31, 31), 0);
+        let wood = channel(ResourceType::Wood, PheromoneKind::Collect);
+        // dispersed at another tick of the period
+        assert!(intensity(wood, 5, 5) > intensity(wood, 8, 5));
+        assert!(intensity(wood, 11, 5) > 0);
+        let gather = channel(ResourceType::Wheat, PheromoneKind::Gather);
+        assert_eq!(intensity(gather, 24, 24), 0);
+        assert!(intensity(gather, 23, 24) > intensity(gather, 21, 24));
+        assert!(intensity(gather, 21, 24) > 0);
+        assert!(intensity(gather, 27, 24) > 0);
+        assert_eq!(intensity(gather, 29, 24), 0);
+        assert!(intensity(stone, 16, 2) > intensity(stone, 16, 5));
+        let algae = channel(ResourceType::Algae, PheromoneKind::Gather);
+        assert!((0..32).all(|y| (0..32).all(|x| intensity(algae, x, y) == 0)));
+
+        // a unit climbing the collect field comes and stays close to the hive
+        let warrior = simulation
+            .try_spawn_unit(Coord::new(12, 12), UnitKind::Warrior, TeamId(0))
+            .unwrap();
+        simulation.units[warrior.0 as usize].policy = MovePolicy::Climb(collect);
+        let distance_to_hive = |position: Coord| {
+            let distance = |v: i16| (4 - v).max(v - 5).max(0);
+            distance(position.x).max(distance(position.y))
+        };
+        let mut distances = Vec::new();
+        for _ in 0..1000 {
+            simulation.step();
+            distances.push(distance_to_hive(simulation.unit(warrior).position));
+        }
+        assert_eq!(distances[0], 7);
+        assert!(distances[300..].iter().all(|&distance| distance <= 2));
+    }
+
+    #[test]
+    fn units_follow_trails() {
+        // a corridor one tile high, in which units cannot pass each other
+        let mut terrain = TerrainMap::new(40, 5);
+        for x in 0..40 {
+            terrain.set(Coord::new(x, 2), TerrainType::Sand);
+        }
+        let mut simulation = Simulation::new(terrain, 0);
+        let follower = simulation
+            .try_spawn_unit(Coord::new(1, 2), UnitKind::Warrior, TeamId(0))
+            .unwrap();
+        let leader = simulation
+            .try_spawn_unit(Coord::new(3, 2), UnitKind::Warrior, TeamId(0))
+            .unwrap();
+        let trail = PheromoneChannel::from_index(3).unwrap();
+        simulation.apply(&Command::SetPolicy {
+            unit: leader,
+            policy: MovePolicy::Descend(trail),
+        });
+        simulation.apply(&Command::SetPolicy {
+            unit: follower,
+            policy: MovePolicy::Climb(trail),
+        });
+        assert_eq!(simulation.unit(follower).policy, MovePolicy::Climb(trail));
+        // out of range units are ignored
+        simulation.apply(&Command::SetPolicy {
+            unit: UnitId(2),
+            policy: MovePolicy::Climb(trail),
+        });
+
+        // the leader flees its own trail, and the follower climbs it behind the leader
+        for _ in 0..1000 {
+            simulation.step();
+        }
+        let leader = simulation.unit(leader).position.x;
+        let follower = simulation.unit(follower).position.x;
+        assert!(leader > 30, "leader at {leader}");
+        assert!(follower > 25, "follower at {follower}");
+        let intensity = |x| {
+            simulation.teams[0]
+                .pheromone_map(trail)
+                .get(Coord::new(x, 2))
+        };
+        assert!(intensity(leader) > intensity(follower));
+        assert!(intensity(follower) > intensity(5));
+    }
+}
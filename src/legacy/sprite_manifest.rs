@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use super::sprites::Sprite;
+
+/// What a sprite of the sprite bank is used for, from its index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpriteGroup {
+    Unit,
+    Terrain,
+    Building,
+    ConstructionSite,
+    Other,
+}
+impl SpriteGroup {
+    pub fn of_index(index: usize) -> Self {
+        match index {
+            0..=191 => SpriteGroup::Unit,
+            192..=355 => SpriteGroup::Terrain,
+            370..=418 => SpriteGroup::Building,
+            492..=498 => SpriteGroup::ConstructionSite,
+            _ => SpriteGroup::Other,
+        }
+    }
+}
+
+/// The description of a sprite, stored as an image file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpriteEntry {
+    pub index: usize,
+    /// Name of the image file, relative to the manifest
+    pub file: String,
+    /// Width without the padding column
+    pub width: u32,
+    pub height: u32,
+    pub x_extra: u32,
+    pub y_extra: u32,
+    pub group: SpriteGroup,
+}
+
+/// The description of all sprites of a sprite bank, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpriteManifest {
+    #[serde(rename = "sprite")]
+    pub sprites: Vec<SpriteEntry>,
+}
+impl SpriteManifest {
+    /// Describe the sprites, each stored in a file named after its index, such as 042.png
+    pub fn from_sprites(sprites: &[Sprite]) -> Self {
+        let sprites = sprites
+            .iter()
+            .enumerate()
+            .map(|(index, sprite)| SpriteEntry {
+                index,
+                file: format!("{index:03}.png"),
+                width: sprite.stored_width(),
+                height: sprite.height,
+                x_extra: sprite.x_extra,
+                y_extra: sprite.y_extra,
+                group: SpriteGroup::of_index(index),
+            })
+            .collect();
+        Self { sprites }
+    }
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_round_trip() {
+        let sprite = Sprite {
+            width: 4,
+            height: 2,
+            x_extra: 2,
+            y_extra: 1,
+            pixels: vec![0; 8],
+            padded: true,
+        };
+        let manifest = SpriteManifest::from_sprites(&[sprite.clone(), sprite]);
+        assert_eq!(manifest.sprites[1].file, "001.png");
+        assert_eq!(manifest.sprites[1].width, 3);
+        let text = manifest.to_toml().unwrap();
+        assert!(text.contains("[[sprite]]"));
+        assert!(text.contains("group = \"unit\""));
+        assert_eq!(SpriteManifest::from_toml(&text).unwrap(), manifest);
+    }
+}
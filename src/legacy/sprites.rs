@@ -1,19 +1,165 @@
+use std::{fs::File, io, io::Read, path::Path};
+
+#[cfg(all(feature = "bevy", feature = "embedded-assets"))]
 use super::palette::PALETTE;
-use bevy::render::{
-    render_resource::{Extent3d, TextureDimension, TextureFormat},
-    texture::Image,
+use super::palette::{self, Palette};
+#[cfg(feature = "bevy")]
+use bevy::{
+    prelude::Vec2,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::Image,
+    },
+    sprite::Anchor,
 };
 use log::debug;
 
-pub fn load() -> Vec<Image> {
-    let bytes = include_bytes!("../../assets/glob1-sprites.bin");
+/// A sprite whose pixels are entries in the palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    /// Width of the pixel buffer, including the padding column if any
+    pub width: u32,
+    pub height: u32,
+    /// Horizontal offset in pixels from the left of the sprite to the left of its tile, when drawn on the map
+    pub x_extra: u32,
+    /// Vertical offset in pixels from the top of the sprite to the top of its tile, when drawn on the map
+    pub y_extra: u32,
+    /// Palette entries, row by row
+    pub pixels: Vec<u8>,
+    /// Whether the last column is padding, because the stored width is odd
+    pub padded: bool,
+}
+impl Sprite {
+    /// Quantize 8-bit RGBA pixels to the nearest entries of the palette, padding odd widths.
+    /// If transparent is true, pixels with alpha below 128 become entry 0, which other pixels avoid.
+    pub fn from_rgba(
+        width: u32,
+        height: u32,
+        data: &[u8],
+        palette: &Palette,
+        transparent: bool,
+    ) -> Self {
+        assert_eq!(data.len(), width as usize * height as usize * 4);
+        let padded = width & 0x1 == 1;
+        let mut pixels = Vec::with_capacity((width as usize + 1) * height as usize);
+        for row in data.chunks_exact(width as usize * 4) {
+            pixels.extend(row.chunks_exact(4).map(|rgba| {
+                if transparent && rgba[3] < 128 {
+                    0
+                } else {
+                    palette::nearest_entry(palette, [rgba[0], rgba[1], rgba[2]], transparent)
+                }
+            }));
+            if padded {
+                pixels.push(0);
+            }
+        }
+        Self {
+            width: width + padded as u32,
+            height,
+            x_extra: 0,
+            y_extra: 0,
+            pixels,
+            padded,
+        }
+    }
+    /// Width as stored in the header, without the padding column
+    pub fn stored_width(&self) -> u32 {
+        self.width - self.padded as u32
+    }
+    /// Returns the palette entry of a pixel
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+    /// Convert to 8-bit RGBA pixels using the palette, the padding column being transparent.
+    /// If transparent is true, entry 0 is transparent as well.
+    pub fn to_rgba(&self, palette: &Palette, transparent: bool) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for (i, &entry_id) in self.pixels.iter().enumerate() {
+            let ignore_pixel = self.padded && i as u32 % self.width + 1 == self.width;
+            // assuming entry 0 is transparent
+            if ignore_pixel || transparent && entry_id == 0 {
+                data.extend([0, 0, 0, 0]);
+            } else {
+                let pixel = &palette[entry_id as usize];
+                data.extend([pixel.r, pixel.g, pixel.b, 255]);
+            }
+        }
+        data
+    }
+    /// The anchor placing the center of the sprite's tile on the transform, as used by `grid_to_world`.
+    ///
+    /// This places the top-left of the sprite x_extra and y_extra pixels left and up from the top-left of the tile.
+    #[cfg(feature = "bevy")]
+    pub fn anchor(&self) -> Anchor {
+        let (width, height) = (self.width as f32, self.height as f32);
+        Anchor::Custom(Vec2::new(
+            (self.x_extra as f32 + 16.0) / width - 0.5,
+            0.5 - (self.y_extra as f32 + 16.0) / height,
+        ))
+    }
+}
+
+/// Whether the sprite at index in the sprite bank uses transparency, terrain doesn't
+pub fn has_transparency(index: usize) -> bool {
+    !(192..=355).contains(&index)
+}
+
+/// An error while loading a sprite bank, with the number of the sprite and the offset in bytes of its header
+#[derive(Debug)]
+pub enum SpriteLoadError {
+    /// The input could not be read
+    Io(io::Error),
+    /// The input ended within the 8 bytes of the header of a sprite
+    TruncatedHeader { sprite: usize, offset: usize },
+    /// The input ended before all pixels of a sprite
+    TruncatedPixels {
+        sprite: usize,
+        offset: usize,
+        expected: usize,
+        available: usize,
+    },
+}
+impl std::fmt::Display for SpriteLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SpriteLoadError::*;
+        match self {
+            Io(error) => write!(f, "cannot read sprites: {error}"),
+            TruncatedHeader { sprite, offset } => write!(
+                f,
+                "truncated header of sprite {sprite} at offset {offset}"
+            ),
+            TruncatedPixels {
+                sprite,
+                offset,
+                expected,
+                available,
+            } => write!(
+                f,
+                "truncated pixels of sprite {sprite} at offset {offset}: expected {expected} bytes, {available} available"
+            ),
+        }
+    }
+}
+impl std::error::Error for SpriteLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpriteLoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Decode a sprite bank into palette-indexed sprites
+pub fn decode(bytes: &[u8]) -> Result<Vec<Sprite>, SpriteLoadError> {
     let l = bytes.len();
     let mut i = 0;
     let mut n = 0;
-    let mut images = Vec::new();
+    let mut sprites = Vec::new();
     while i < l {
+        let offset = i;
         if i + 8 > l {
-            panic!("End of file before complete header!")
+            return Err(SpriteLoadError::TruncatedHeader { sprite: n, offset });
         }
         let get_usize = |i: &mut usize| {
             let v = (bytes[*i] as u32) << 8 | bytes[*i + 1] as u32;
@@ -35,40 +181,187 @@ pub fn load() -> Vec<Image> {
             if last_col_pad { " (padding)" } else { "" }
         );
         let pixel_count = w as usize * h as usize;
-        let mut data = Vec::with_capacity(pixel_count * 4);
-        // terrain doesn't have transparency
-        let has_transparency = !(192..=355).contains(&n);
-        for _y in 0..h {
-            for x in 0..w {
-                let entry_id = bytes[i] as usize;
-                let ignore_pixel = x + 1 == w && last_col_pad;
-                // assuming entry 0 is transparent
-                if ignore_pixel || has_transparency && entry_id == 0 {
-                    data.push(0);
-                    data.push(0);
-                    data.push(0);
-                    data.push(0);
-                } else {
-                    let pixel = &PALETTE[entry_id];
-                    data.push(pixel.r);
-                    data.push(pixel.g);
-                    data.push(pixel.b);
-                    data.push(255u8);
-                }
-                i += 1;
-            }
+        let pixels = bytes
+            .get(i..i + pixel_count)
+            .ok_or(SpriteLoadError::TruncatedPixels {
+                sprite: n,
+                offset,
+                expected: pixel_count,
+                available: l - i,
+            })?
+            .to_vec();
+        i += pixel_count;
+        sprites.push(Sprite {
+            width: w,
+            height: h,
+            x_extra,
+            y_extra,
+            pixels,
+            padded: last_col_pad,
+        });
+        n += 1;
+    }
+    Ok(sprites)
+}
+
+/// Encode sprites into a sprite bank, which `decode` reads back unchanged.
+///
+/// Panics if a size or offset does not fit in 16 bits.
+pub fn encode(sprites: &[Sprite]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for sprite in sprites {
+        for value in [
+            sprite.y_extra,
+            sprite.x_extra,
+            sprite.height,
+            sprite.stored_width(),
+        ] {
+            let value = u16::try_from(value).expect("sprite header value does not fit in 16 bits");
+            bytes.extend(value.to_be_bytes());
         }
-        images.push(Image::new(
-            Extent3d {
-                width: w,
-                height: h,
-                depth_or_array_layers: 1,
-            },
-            TextureDimension::D2,
-            data,
-            TextureFormat::Rgba8UnormSrgb, // Rgba8Unorm doesn't work with tilemap
+        bytes.extend(&sprite.pixels);
+    }
+    bytes
+}
+
+/// Read and decode a sprite bank
+pub fn read(mut input: impl Read) -> Result<Vec<Sprite>, SpriteLoadError> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes).map_err(SpriteLoadError::Io)?;
+    decode(&bytes)
+}
+
+/// Load and decode a sprite bank from a file
+pub fn load_from_path(path: impl AsRef<Path>) -> Result<Vec<Sprite>, SpriteLoadError> {
+    read(File::open(path).map_err(SpriteLoadError::Io)?)
+}
+
+/// Decode the sprites of the game embedded in the binary
+#[cfg(feature = "embedded-assets")]
+pub fn load_indexed() -> Result<Vec<Sprite>, SpriteLoadError> {
+    decode(include_bytes!("../../assets/glob1-sprites.bin"))
+}
+
+/// Convert a sprite to a Bevy image using the palette
+#[cfg(feature = "bevy")]
+pub fn to_image(sprite: &Sprite, palette: &Palette, transparent: bool) -> Image {
+    Image::new(
+        Extent3d {
+            width: sprite.width,
+            height: sprite.height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        sprite.to_rgba(palette, transparent),
+        TextureFormat::Rgba8UnormSrgb, // Rgba8Unorm doesn't work with tilemap
+    )
+}
+
+/// Load the sprites of the game embedded in the binary as Bevy images
+#[cfg(all(feature = "bevy", feature = "embedded-assets"))]
+pub fn load() -> Result<Vec<Image>, SpriteLoadError> {
+    Ok(load_indexed()?
+        .iter()
+        .enumerate()
+        .map(|(n, sprite)| to_image(sprite, &PALETTE, has_transparency(n)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::legacy::palette::PaletteEntry;
+
+    fn header(y_extra: u16, x_extra: u16, height: u16, width: u16) -> Vec<u8> {
+        [y_extra, x_extra, height, width]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+
+    /// A bank with a 3 x 2 sprite, stored padded to 4 x 2, followed by a 2 x 1 sprite
+    fn test_bank() -> Vec<u8> {
+        let mut bytes = header(1, 2, 2, 3);
+        bytes.extend([1, 2, 3, 0, 4, 5, 6, 0]);
+        bytes.extend(header(0, 0, 1, 2));
+        bytes.extend([0, 7]);
+        bytes
+    }
+
+    #[test]
+    fn decode_padding() {
+        let sprites = decode(&test_bank()).unwrap();
+        assert_eq!(sprites.len(), 2);
+        let sprite = &sprites[0];
+        assert_eq!((sprite.width, sprite.height), (4, 2));
+        assert_eq!((sprite.x_extra, sprite.y_extra), (2, 1));
+        assert!(sprite.padded);
+        assert_eq!(sprite.pixel(2, 1), 6);
+        let mut palette = [PaletteEntry::default(); 256];
+        palette[3] = PaletteEntry { r: 1, g: 2, b: 3 };
+        let rgba = sprite.to_rgba(&palette, false);
+        assert_eq!(&rgba[8..16], &[1, 2, 3, 255, 0, 0, 0, 0]);
+        let sprite = &sprites[1];
+        assert_eq!((sprite.width, sprite.height), (2, 1));
+        assert!(!sprite.padded);
+        assert_eq!(sprite.pixels, vec![0, 7]);
+        assert_eq!(&sprite.to_rgba(&palette, true)[..4], &[0, 0, 0, 0]);
+        assert_eq!(&sprite.to_rgba(&palette, false)[..4], &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let bytes = test_bank();
+        assert_eq!(encode(&decode(&bytes).unwrap()), bytes);
+    }
+
+    #[test]
+    fn from_rgba() {
+        let mut palette = [PaletteEntry::default(); 256];
+        palette[1] = PaletteEntry { r: 255, g: 0, b: 0 };
+        palette[2] = PaletteEntry { r: 0, g: 0, b: 255 };
+        #[rustfmt::skip]
+        let data = [
+            250, 10, 0, 255,   0, 0, 0, 0,   0, 0, 0, 255,
+            0, 0, 200, 255,   255, 0, 0, 100,   0, 0, 0, 0,
+        ];
+        let sprite = Sprite::from_rgba(3, 2, &data, &palette, true);
+        assert_eq!((sprite.width, sprite.stored_width()), (4, 3));
+        assert!(sprite.padded);
+        // black is entry 0 as well, but it is reserved for transparency
+        assert_eq!(sprite.pixels, vec![1, 0, 3, 0, 2, 0, 0, 0]);
+        let sprite = Sprite::from_rgba(3, 2, &data, &palette, false);
+        assert_eq!(sprite.pixels, vec![1, 0, 0, 0, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let bytes = test_bank();
+        assert!(decode(&[]).unwrap().is_empty());
+        assert!(matches!(
+            decode(&bytes[..20]),
+            Err(SpriteLoadError::TruncatedHeader {
+                sprite: 1,
+                offset: 16
+            })
+        ));
+        assert!(matches!(
+            decode(&bytes[..12]),
+            Err(SpriteLoadError::TruncatedPixels {
+                sprite: 0,
+                offset: 0,
+                expected: 8,
+                available: 4
+            })
+        ));
+        assert!(matches!(
+            decode(&bytes[..25]),
+            Err(SpriteLoadError::TruncatedPixels {
+                sprite: 1,
+                offset: 16,
+                expected: 2,
+                available: 1
+            })
         ));
-        n += 1;
     }
-    images
 }
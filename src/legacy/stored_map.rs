@@ -1,11 +1,59 @@
 use log::{debug, error, trace};
-use rand::{prelude::ThreadRng, Rng};
-use std::io::{Error, ErrorKind, Read};
+use rand::Rng;
+use std::{
+    io::{BufReader, Bytes, Error, ErrorKind, Read, Write},
+    iter::Peekable,
+};
 
-use super::{grid::Coord, terrain::TerrainMap};
+use super::{
+    grid::{Coord, Grid2D},
+    terrain::{TerrainClass, TerrainMap},
+};
+
+/// The header in front of the maps from the released game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapHeader {
+    /// The first 4 bytes of the file
+    pub signature: [u8; 4],
+    /// The 3 bytes following the signature
+    pub extra: [u8; 3],
+}
+
+/// The variants of the map format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MapFormat {
+    /// Detect the header from the first byte, which for headerless maps is a valid compression type
+    #[default]
+    Auto,
+    /// Maps without header, such as the ones in the maps directory
+    Headerless,
+    /// Maps from the released game, starting with a header
+    Released,
+}
+
+/// Options for loading a map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub format: MapFormat,
+    /// Number of blocks of 16x16 tiles along x, as the size of the map is not stored
+    pub blocks_x: usize,
+    /// Number of blocks of 16x16 tiles along y, as the size of the map is not stored
+    pub blocks_y: usize,
+}
+impl Default for LoadOptions {
+    /// Options for 1024x1024 maps of any format, as used by the game
+    fn default() -> Self {
+        Self {
+            format: MapFormat::Auto,
+            blocks_x: 64,
+            blocks_y: 64,
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StoredMap {
+    pub header: Option<MapHeader>,
     pub terrain: TerrainMap,
     pub queen_positions: Vec<Coord>,
     pub view_position: Coord,
@@ -16,57 +64,240 @@ impl std::fmt::Display for StoredMap {
             "{} queens, start view {:?}",
             self.queen_positions.len(),
             self.view_position
-        ))
+        ))?;
+        if let Some(header) = &self.header {
+            f.write_fmt(format_args!(
+                ", header {:?} {:?}",
+                header.signature, header.extra
+            ))?;
+        }
+        Ok(())
     }
 }
 
-/// When a tile is compressed, only its type (e.g. pure water) is stored.
-/// This struct allows to sample actual tiles (e.g. pure water third sprite).
-struct TerrainTileRange(u8, u8);
-impl TerrainTileRange {
-    fn decode(encoded: u8) -> Self {
-        if encoded < 31 {
-            Self(encoded * 4, 4)
-        } else {
-            Self(4 + (10 * (encoded - 19)), 10)
+/// An error while loading a map, with the offset in bytes from the start of the input where it occurred
+#[derive(Debug)]
+pub enum MapLoadError {
+    /// The input could not be read
+    Io(Error),
+    /// The input ended before the map was complete, while decoding the given block if any
+    UnexpectedEof {
+        offset: usize,
+        block: Option<(usize, usize)>,
+    },
+    /// A block, at given block coordinates, uses an unknown compression type
+    UnknownCompressionType {
+        offset: usize,
+        block: (usize, usize),
+        compression_type: u8,
+    },
+    /// A run of linear compression type A or B goes past the 256 tiles of its block
+    RunOverflow {
+        offset: usize,
+        block: (usize, usize),
+        compression_type: u8,
+        filled: usize,
+        count: usize,
+    },
+    /// A compressed block uses a class without tiles
+    InvalidTerrainClass {
+        offset: usize,
+        block: (usize, usize),
+        class: u8,
+    },
+    /// The view position is not set
+    MissingViewPosition { offset: usize },
+}
+impl std::fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use MapLoadError::*;
+        match self {
+            Io(error) => write!(f, "cannot read map: {error}"),
+            UnexpectedEof {
+                offset,
+                block: None,
+            } => write!(f, "unexpected end of file at offset {offset}"),
+            UnexpectedEof {
+                offset,
+                block: Some(block),
+            } => write!(
+                f,
+                "unexpected end of file at offset {offset}, in block {block:?}"
+            ),
+            UnknownCompressionType {
+                offset,
+                block,
+                compression_type,
+            } => write!(
+                f,
+                "unknown compression type {compression_type} at offset {offset}, in block {block:?}"
+            ),
+            RunOverflow {
+                offset,
+                block,
+                compression_type,
+                filled,
+                count,
+            } => write!(
+                f,
+                "run of {count} tiles after {filled} tiles overflows the 256 tiles of block {block:?}, at offset {offset} (compression type {compression_type})"
+            ),
+            InvalidTerrainClass {
+                offset,
+                block,
+                class,
+            } => write!(
+                f,
+                "invalid terrain class {class} at offset {offset}, in block {block:?}"
+            ),
+            MissingViewPosition { offset } => {
+                write!(f, "missing view position at offset {offset}")
+            }
         }
     }
-    fn sample_tile(&self, rng: &mut ThreadRng) -> u8 {
-        self.0 + rng.gen_range(0..self.1)
+}
+impl std::error::Error for MapLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MapLoadError::Io(error) => Some(error),
+            _ => None,
+        }
     }
 }
 
-pub fn load(input: impl Read) -> Result<StoredMap, Error> {
-    let mut bytes = input.bytes();
-    debug!("Loading map");
-    let mut get_u8 = || {
-        bytes
+/// Reads the bytes of a map, keeping track of the offset for error reporting
+struct MapReader<R: Read> {
+    bytes: Peekable<Bytes<BufReader<R>>>,
+    offset: usize,
+    /// The block being decoded, if any
+    block: Option<(usize, usize)>,
+}
+impl<R: Read> MapReader<R> {
+    fn new(input: R) -> Self {
+        Self {
+            bytes: BufReader::new(input).bytes().peekable(),
+            offset: 0,
+            block: None,
+        }
+    }
+    fn peek_u8(&mut self) -> Option<u8> {
+        match self.bytes.peek() {
+            Some(Ok(byte)) => Some(*byte),
+            _ => None,
+        }
+    }
+    fn get_u8(&mut self) -> Result<u8, MapLoadError> {
+        let byte = self
+            .bytes
             .next()
             .transpose()
-            .and_then(|b| b.ok_or_else(|| Error::from(ErrorKind::UnexpectedEof)))
+            .map_err(MapLoadError::Io)?
+            .ok_or(MapLoadError::UnexpectedEof {
+                offset: self.offset,
+                block: self.block,
+            })?;
+        self.offset += 1;
+        Ok(byte)
+    }
+    /// Read the class of compressed tiles, which must be valid for its tiles to be sampled
+    fn get_class(&mut self, block: (usize, usize)) -> Result<TerrainClass, MapLoadError> {
+        let offset = self.offset;
+        let class = TerrainClass(self.get_u8()?);
+        if !class.is_valid() {
+            return Err(MapLoadError::InvalidTerrainClass {
+                offset,
+                block,
+                class: class.0,
+            });
+        }
+        Ok(class)
+    }
+    // helpers for 16 bit types
+    fn get_u16_be(&mut self) -> Result<u16, MapLoadError> {
+        let b_high = self.get_u8()?;
+        let b_low = self.get_u8()?;
+        Ok((b_high as u16) << 8 | (b_low as u16))
+    }
+    fn get_coord(&mut self) -> Result<Option<Coord>, MapLoadError> {
+        let x = self.get_u16_be()?;
+        let y = self.get_u16_be()?;
+        Ok(if x != u16::MAX && y != u16::MAX {
+            Some(Coord::new(x as i16, y as i16))
+        } else {
+            None
+        })
+    }
+}
+
+/// Load a 1024x1024 map, detecting whether it has a header.
+///
+/// The rng is used to sample the tiles of compressed blocks, so loading with the same seed gives the same terrain.
+pub fn load(input: impl Read, rng: &mut impl Rng) -> Result<StoredMap, MapLoadError> {
+    load_with_options(input, LoadOptions::default(), rng)
+}
+
+/// Load a map of the given format and size
+pub fn load_with_options(
+    input: impl Read,
+    options: LoadOptions,
+    rng: &mut impl Rng,
+) -> Result<StoredMap, MapLoadError> {
+    load_with_compression_types(input, options, rng).map(|(map, _)| map)
+}
+
+/// Load a map of the given format and size, also returning the compression type of each block in the order of storage.
+/// Blocks filled by a multi-tile encoding have its compression type.
+pub fn load_with_compression_types(
+    input: impl Read,
+    options: LoadOptions,
+    rng: &mut impl Rng,
+) -> Result<(StoredMap, Vec<u8>), MapLoadError> {
+    let mut reader = MapReader::new(input);
+    debug!("Loading map");
+    let has_header = match options.format {
+        MapFormat::Auto => match reader.peek_u8() {
+            Some(first) => !(1..=5).contains(&first),
+            None => false,
+        },
+        MapFormat::Headerless => false,
+        MapFormat::Released => true,
     };
     // This allows to load the maps from the released game
-    /*let header = [get_u8()?, get_u8()?, get_u8()?, get_u8()?];
-    get_u8()?;
-    get_u8()?;
-    get_u8()?;*/
+    let header = if has_header {
+        let mut signature = [0; 4];
+        for byte in &mut signature {
+            *byte = reader.get_u8()?;
+        }
+        let mut extra = [0; 3];
+        for byte in &mut extra {
+            *byte = reader.get_u8()?;
+        }
+        debug!("Found header {signature:?} {extra:?}");
+        Some(MapHeader { signature, extra })
+    } else {
+        None
+    };
 
-    let mut map = box_array![[0; 1024]; 1024];
-    let mut rng = rand::thread_rng();
+    let (blocks_x, blocks_y) = (options.blocks_x, options.blocks_y);
+    let mut terrain = TerrainMap::new(blocks_x * 16, blocks_y * 16);
+    let position = |x: usize, y: usize| Coord::new(x as i16, y as i16);
+    let mut compression_types = Vec::with_capacity(blocks_x * blocks_y);
     let mut multi_tiles: Option<(u8, i32)> = None;
-    for tile_x in 0..64 {
+    for tile_x in 0..blocks_x {
         let base_x = tile_x * 16;
-        for tile_y in 0..64 {
+        for tile_y in 0..blocks_y {
             let base_y = tile_y * 16;
             trace!("Processing tile {tile_x} {tile_y}");
+            reader.block = Some((tile_x, tile_y));
             // we are processing a multi-tile encoding
             if let Some((encoded, count)) = &mut multi_tiles {
                 trace!("  part of multi-tile encoding");
+                compression_types.push(5);
                 // fill one tile
-                let tile_range = TerrainTileRange::decode(*encoded);
+                let class = TerrainClass(*encoded);
                 for dx in 0..16 {
                     for dy in 0..16 {
-                        map[base_x + dx][base_y + dy] = tile_range.sample_tile(&mut rng);
+                        terrain.set_class(position(base_x + dx, base_y + dy), class, rng);
                     }
                 }
                 // are there tiles left to process?
@@ -78,107 +309,447 @@ pub fn load(input: impl Read) -> Result<StoredMap, Error> {
                 continue;
             }
             // we are processing a new tile
-            let compression_type = get_u8()?;
+            let offset = reader.offset;
+            let compression_type = reader.get_u8()?;
             trace!("  compressed with mode {compression_type}");
+            compression_types.push(compression_type);
             match compression_type {
                 // uncompressed
                 1 => {
                     for dx in 0..16 {
                         for dy in 0..16 {
-                            map[base_x + dx][base_y + dy] = get_u8()?;
+                            let tile = reader.get_u8()?;
+                            terrain.set_tile(position(base_x + dx, base_y + dy), tile);
                         }
                     }
                 }
                 // constant tile type
                 2 => {
-                    let tile_range = TerrainTileRange::decode(get_u8()?);
+                    let class = reader.get_class((tile_x, tile_y))?;
                     for dx in 0..16 {
                         for dy in 0..16 {
-                            map[base_x + dx][base_y + dy] = tile_range.sample_tile(&mut rng);
+                            terrain.set_class(position(base_x + dx, base_y + dy), class, rng);
                         }
                     }
                 }
-                // linear compression type A
-                3 => {
+                // linear compression type A (3) and B (4)
+                3 | 4 => {
                     let mut filled = 0;
                     while filled < 256 {
-                        let tile_range = TerrainTileRange::decode(get_u8()?);
-                        let count = get_u8()? as usize;
-                        for i in filled..filled + count {
-                            let dx = i / 16;
-                            let dy = i % 16;
-                            map[base_x + dx][base_y + dy] = tile_range.sample_tile(&mut rng);
+                        let offset = reader.offset;
+                        let class = reader.get_class((tile_x, tile_y))?;
+                        let count = reader.get_u8()? as usize;
+                        if filled + count > 256 {
+                            return Err(MapLoadError::RunOverflow {
+                                offset,
+                                block: (tile_x, tile_y),
+                                compression_type,
+                                filled,
+                                count,
+                            });
                         }
-                        filled += count;
-                    }
-                }
-                // linear compression type B
-                4 => {
-                    let mut filled = 0;
-                    while filled < 256 {
-                        let tile_range = TerrainTileRange::decode(get_u8()?);
-                        let count = get_u8()? as usize;
                         for i in filled..filled + count {
-                            let dx = i % 16;
-                            let dy = i / 16;
-                            map[base_x + dx][base_y + dy] = tile_range.sample_tile(&mut rng);
+                            let (dx, dy) = if compression_type == 3 {
+                                (i / 16, i % 16)
+                            } else {
+                                (i % 16, i / 16)
+                            };
+                            terrain.set_class(position(base_x + dx, base_y + dy), class, rng);
                         }
                         filled += count;
                     }
                 }
                 // multi-tile constant tile type
                 5 => {
-                    let encoded = get_u8()?;
-                    let count = get_u8()? as i32;
-                    trace!("  decompressing {count} tiles with type {encoded}");
+                    let class = reader.get_class((tile_x, tile_y))?;
+                    let count = reader.get_u8()? as i32;
+                    trace!("  decompressing {count} tiles with type {}", class.0);
                     // fill one tile
-                    let tile_range = TerrainTileRange::decode(encoded);
                     for dx in 0..16 {
                         for dy in 0..16 {
-                            map[base_x + dx][base_y + dy] = tile_range.sample_tile(&mut rng);
+                            terrain.set_class(position(base_x + dx, base_y + dy), class, rng);
                         }
                     }
                     // schedule the rest
                     if count > 1 {
-                        multi_tiles = Some((encoded, count - 1));
+                        multi_tiles = Some((class.0, count - 1));
                     }
                 }
-                ty => {
-                    error!("Found unknown compression type {ty}");
-                    return Err(Error::from(ErrorKind::InvalidData));
+                compression_type => {
+                    error!("Found unknown compression type {compression_type}");
+                    return Err(MapLoadError::UnknownCompressionType {
+                        offset,
+                        block: (tile_x, tile_y),
+                        compression_type,
+                    });
                 }
             }
         }
     }
-    // helpers for 16 bit types
-    let mut get_u16_be = || {
-        let b_high = get_u8();
-        b_high.and_then(|b_high| get_u8().map(|b_low| (b_high as u16) << 8 | (b_low as u16)))
-    };
-    let mut get_coord = || {
-        let x = get_u16_be();
-        x.and_then(|x| {
-            get_u16_be().map(|y| {
-                if x != u16::MAX && y != u16::MAX {
-                    Some(Coord::new(x as i16, y as i16))
-                } else {
-                    None
-                }
-            })
-        })
-    };
+    reader.block = None;
     // load queen positions
     let mut queen_positions = Vec::new();
     for _ in 0..8 {
-        if let Some(coord) = get_coord()? {
+        if let Some(coord) = reader.get_coord()? {
             queen_positions.push(coord)
         }
     }
     // load view position
-    let view_position = get_coord()?.unwrap();
-    Ok(StoredMap {
-        terrain: TerrainMap(map),
+    let offset = reader.offset;
+    let view_position = reader
+        .get_coord()?
+        .ok_or(MapLoadError::MissingViewPosition { offset })?;
+    let map = StoredMap {
+        header,
+        terrain,
         queen_positions,
         view_position,
-    })
+    };
+    Ok((map, compression_types))
+}
+
+/// Encoded types of the tiles of the block at base_x, base_y, in the order of linear compression type A.
+/// Returns None if any tile was set explicitly, as then the block must be stored uncompressed.
+fn block_types(map: &TerrainMap, base_x: usize, base_y: usize) -> Option<[u8; 256]> {
+    let mut types = [0; 256];
+    for (i, ty) in types.iter_mut().enumerate() {
+        let position = Coord::new((base_x + i / 16) as i16, (base_y + i % 16) as i16);
+        *ty = map.sampled_class(position)?.0;
+    }
+    Some(types)
+}
+
+/// Run-length encode types as (type, count) pairs, as used by the linear compression types
+fn runs(types: impl Iterator<Item = u8>) -> Vec<(u8, u8)> {
+    let mut runs: Vec<(u8, u8)> = Vec::new();
+    for ty in types {
+        match runs.last_mut() {
+            Some((last_ty, count)) if *last_ty == ty && *count < u8::MAX => *count += 1,
+            _ => runs.push((ty, 1)),
+        }
+    }
+    runs
+}
+
+/// Save a map in the format read by `load`, using for each block the smallest encoding.
+///
+/// Blocks whose tiles were all sampled from their class are compressed, as only their class is stored,
+/// and their tiles will be re-sampled when loading. Blocks containing explicitly set tiles are stored uncompressed.
+pub fn save(map: &StoredMap, mut output: impl Write) -> Result<(), Error> {
+    debug!("Saving map");
+    if map.queen_positions.len() > 8 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "at most 8 queen positions can be stored",
+        ));
+    }
+    let (width, height) = (map.terrain.width(), map.terrain.height());
+    if width % 16 != 0 || height % 16 != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "the size of the map must be a multiple of 16",
+        ));
+    }
+    let blocks_y = height / 16;
+    let mut bytes = Vec::new();
+    if let Some(header) = &map.header {
+        bytes.extend(header.signature);
+        bytes.extend(header.extra);
+    }
+    // compute the block types once, in the order of storage
+    let blocks: Vec<_> = (0..width / 16)
+        .flat_map(|tile_x| (0..blocks_y).map(move |tile_y| (tile_x, tile_y)))
+        .map(|(tile_x, tile_y)| block_types(&map.terrain, tile_x * 16, tile_y * 16))
+        .collect();
+    let constant_type = |types: &Option<[u8; 256]>| {
+        let types = types.as_ref()?;
+        let first = types[0];
+        types.iter().all(|&ty| ty == first).then_some(first)
+    };
+    let mut block_index = 0;
+    while block_index < blocks.len() {
+        trace!("Processing block {block_index}");
+        if let Some(encoded) = constant_type(&blocks[block_index]) {
+            // count how many following blocks are of the same constant type
+            let count = blocks[block_index..]
+                .iter()
+                .take(u8::MAX as usize)
+                .take_while(|types| constant_type(types) == Some(encoded))
+                .count();
+            if count > 1 {
+                // multi-tile constant tile type
+                trace!("  compressing {count} tiles with type {encoded}");
+                bytes.extend([5, encoded, count as u8]);
+            } else {
+                // constant tile type
+                bytes.extend([2, encoded]);
+            }
+            block_index += count;
+            continue;
+        }
+        let linear = blocks[block_index].as_ref().and_then(|types| {
+            // linear compression type A, iterating y first
+            let runs_a = runs(types.iter().copied());
+            // linear compression type B, iterating x first
+            let runs_b = runs((0..256).map(|i| types[(i % 16) * 16 + i / 16]));
+            let shortest = if runs_a.len() <= runs_b.len() {
+                (3, runs_a)
+            } else {
+                (4, runs_b)
+            };
+            (shortest.1.len() * 2 < 256).then_some(shortest)
+        });
+        if let Some((compression_type, runs)) = linear {
+            trace!("  compressed with mode {compression_type}");
+            bytes.push(compression_type);
+            for (ty, count) in runs {
+                bytes.extend([ty, count]);
+            }
+        } else {
+            // uncompressed
+            let base_x = (block_index / blocks_y * 16) as i16;
+            let base_y = (block_index % blocks_y * 16) as i16;
+            bytes.push(1);
+            for dx in 0..16 {
+                for dy in 0..16 {
+                    bytes.push(map.terrain.tile(Coord::new(base_x + dx, base_y + dy)));
+                }
+            }
+        }
+        block_index += 1;
+    }
+    // helper for coordinates, encoded as two big-endian 16 bit values
+    let mut put_coord = |coord: Option<Coord>| {
+        let (x, y) = coord.map_or((u16::MAX, u16::MAX), |coord| {
+            (coord.x as u16, coord.y as u16)
+        });
+        bytes.extend(x.to_be_bytes());
+        bytes.extend(y.to_be_bytes());
+    };
+    // save queen positions, unused ones are marked as invalid
+    for i in 0..8 {
+        put_coord(map.queen_positions.get(i).copied());
+    }
+    // save view position
+    put_coord(Some(map.view_position));
+    output.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::legacy::grid::Grid;
+
+    fn test_map() -> StoredMap {
+        let mut terrain = TerrainMap::new(1024, 1024);
+        let mut rng = StdRng::seed_from_u64(0);
+        // first block with a checkerboard of water and sand, which must be stored uncompressed
+        for dx in 0..16 {
+            for dy in 0..16 {
+                let tile = if (dx + dy) % 2 == 0 {
+                    dx % 4
+                } else {
+                    60 + dy % 4
+                };
+                terrain.set_tile(Coord::new(dx, dy), tile as u8);
+            }
+        }
+        // second block with a column of sand, compressible with linear compression type A
+        for dy in 0..16 {
+            terrain.set_class(Coord::new(3, 16 + dy), TerrainClass(15), &mut rng);
+        }
+        // third block with a row of grass, compressible with linear compression type B
+        for dx in 0..16 {
+            terrain.set_class(Coord::new(dx, 32 + 5), TerrainClass(26), &mut rng);
+        }
+        StoredMap {
+            header: None,
+            terrain,
+            queen_positions: vec![Coord::new(10, 20), Coord::new(1000, 3)],
+            view_position: Coord::new(512, 256),
+        }
+    }
+
+    #[test]
+    fn save_smallest_encoding() {
+        let mut map = test_map();
+        map.terrain = TerrainMap::new(1024, 1024);
+        let mut bytes = Vec::new();
+        save(&map, &mut bytes).unwrap();
+        // 4096 constant blocks need 17 multi-tile encodings, plus 9 coordinates
+        assert_eq!(bytes.len(), 17 * 3 + 9 * 4);
+        assert_eq!(&bytes[0..3], &[5, 0, 255]);
+        // explicitly set tiles are never compressed
+        map.terrain = TerrainMap::from_tiles(Grid::new(1024, 1024, 0));
+        let mut bytes = Vec::new();
+        save(&map, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4096 * 257 + 9 * 4);
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let map = test_map();
+        let mut bytes = Vec::new();
+        save(&map, &mut bytes).unwrap();
+        assert_eq!(bytes[0], 1);
+        assert_eq!(bytes[257], 3);
+        assert_eq!(bytes[257 + 1 + 3 * 2], 4);
+        let (loaded, compression_types) = load_with_compression_types(
+            bytes.as_slice(),
+            Default::default(),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        assert_eq!(compression_types.len(), 64 * 64);
+        assert_eq!(&compression_types[0..4], &[1, 3, 4, 5]);
+        assert_eq!(loaded.queen_positions, map.queen_positions);
+        assert_eq!(loaded.view_position, map.view_position);
+        assert_eq!(loaded.terrain.classes, map.terrain.classes);
+        for x in 0..16 {
+            for y in 0..16 {
+                let position = Coord::new(x, y);
+                assert_eq!(loaded.terrain.tile(position), map.terrain.tile(position));
+            }
+        }
+        let mut bytes_again = Vec::new();
+        save(&loaded, &mut bytes_again).unwrap();
+        assert_eq!(bytes_again, bytes);
+    }
+
+    #[test]
+    fn save_load_small_map() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut terrain = TerrainMap::new(32, 48);
+        terrain.set_tile(Coord::new(20, 40), 104);
+        terrain.set_class(Coord::new(3, 5), TerrainClass(15), &mut rng);
+        let map = StoredMap {
+            header: None,
+            terrain,
+            queen_positions: vec![Coord::new(1, 2)],
+            view_position: Coord::new(16, 24),
+        };
+        let mut bytes = Vec::new();
+        save(&map, &mut bytes).unwrap();
+        let options = LoadOptions {
+            blocks_x: 2,
+            blocks_y: 3,
+            ..Default::default()
+        };
+        let (loaded, compression_types) =
+            load_with_compression_types(bytes.as_slice(), options, &mut rng).unwrap();
+        assert_eq!(compression_types, vec![3, 5, 5, 5, 5, 1]);
+        assert_eq!(loaded.terrain.width(), 32);
+        assert_eq!(loaded.terrain.height(), 48);
+        let sand = loaded.terrain.sampled_class(Coord::new(3, 5));
+        assert_eq!(sand, Some(TerrainClass(15)));
+        assert_eq!(loaded.terrain.tile(Coord::new(20, 40)), 104);
+        // a map whose size is not a multiple of the block size cannot be saved
+        let mut map = map;
+        map.terrain = TerrainMap::new(30, 48);
+        assert!(save(&map, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn load_deterministic() {
+        let mut bytes = Vec::new();
+        save(&test_map(), &mut bytes).unwrap();
+        let load_seeded = |seed| load(bytes.as_slice(), &mut StdRng::seed_from_u64(seed)).unwrap();
+        let first = load_seeded(42);
+        let second = load_seeded(42);
+        assert_eq!(first.terrain, second.terrain);
+        assert_ne!(first.terrain, load_seeded(43).terrain);
+    }
+
+    #[test]
+    fn load_errors() {
+        let mut bytes = Vec::new();
+        save(&test_map(), &mut bytes).unwrap();
+        let load_bytes = |bytes: &[u8]| load(bytes, &mut rand::thread_rng()).unwrap_err();
+        // truncated file
+        assert!(matches!(
+            load_bytes(&bytes[..100]),
+            MapLoadError::UnexpectedEof {
+                offset: 100,
+                block: Some((0, 0))
+            }
+        ));
+        assert!(matches!(
+            load_bytes(&bytes[..bytes.len() - 2]),
+            MapLoadError::UnexpectedEof { block: None, .. }
+        ));
+        // class without tiles in the second block
+        let mut corrupted = bytes.clone();
+        corrupted[262] = TerrainClass::COUNT;
+        assert!(matches!(
+            load_bytes(&corrupted),
+            MapLoadError::InvalidTerrainClass {
+                offset: 262,
+                block: (0, 1),
+                class: TerrainClass::COUNT
+            }
+        ));
+        // unknown compression type in the third block
+        let mut corrupted = bytes.clone();
+        corrupted[264] = 9;
+        assert!(matches!(
+            load_bytes(&corrupted),
+            MapLoadError::UnknownCompressionType {
+                offset: 264,
+                block: (0, 2),
+                compression_type: 9
+            }
+        ));
+        // run overflow in the second block
+        let mut corrupted = bytes.clone();
+        corrupted[263] = 255;
+        assert!(matches!(
+            load_bytes(&corrupted),
+            MapLoadError::RunOverflow {
+                offset: 262,
+                block: (0, 1),
+                compression_type: 3,
+                filled: 64,
+                count: 255
+            }
+        ));
+        // view position not set
+        let mut corrupted = bytes.clone();
+        let len = corrupted.len();
+        corrupted[len - 4..].fill(0xff);
+        assert!(matches!(
+            load_bytes(&corrupted),
+            MapLoadError::MissingViewPosition { offset } if offset == len - 4
+        ));
+    }
+
+    #[test]
+    fn load_released_header() {
+        let mut map = test_map();
+        map.header = Some(MapHeader {
+            signature: *b"G1MP",
+            extra: [0, 1, 2],
+        });
+        let mut bytes = Vec::new();
+        save(&map, &mut bytes).unwrap();
+        assert_eq!(&bytes[0..7], b"G1MP\0\x01\x02");
+        let loaded = load(bytes.as_slice(), &mut rand::thread_rng()).unwrap();
+        assert_eq!(loaded.header, map.header);
+        assert_eq!(loaded.queen_positions, map.queen_positions);
+        // a header starting with a valid compression type needs an explicit format
+        map.header = Some(MapHeader {
+            signature: [1, 2, 3, 4],
+            extra: [5, 6, 7],
+        });
+        let mut bytes = Vec::new();
+        save(&map, &mut bytes).unwrap();
+        let options = LoadOptions {
+            format: MapFormat::Released,
+            ..Default::default()
+        };
+        let loaded = load_with_options(bytes.as_slice(), options, &mut rand::thread_rng()).unwrap();
+        assert_eq!(loaded.header, map.header);
+        assert_eq!(loaded.view_position, map.view_position);
+    }
 }
@@ -0,0 +1,83 @@
+use super::palette::{Palette, PaletteEntry};
+
+/// Maximum number of teams, one per queen slot of a stored map
+pub const TEAM_COUNT: usize = 8;
+
+/// Palette entries of unit and building sprites showing the team color, the red ramp of the Mac palette
+pub const TEAM_ENTRIES: std::ops::RangeInclusive<u8> = 215..=224;
+
+/// The color of each team, the first one being the original red
+pub const TEAM_COLORS: [[u8; 3]; TEAM_COUNT] = [
+    [255, 0, 0],
+    [0, 96, 255],
+    [0, 200, 0],
+    [255, 220, 0],
+    [0, 220, 220],
+    [220, 0, 220],
+    [255, 128, 0],
+    [230, 230, 230],
+];
+
+/// A copy of the palette in which the team entries are shades of the team color,
+/// keeping the brightness of the original entries.
+/// Panics if team is not below `TEAM_COUNT`.
+pub fn team_palette(palette: &Palette, team: usize) -> Palette {
+    assert!(
+        team < TEAM_COUNT,
+        "team {team} out of the {TEAM_COUNT} teams having a color"
+    );
+    let color = TEAM_COLORS[team];
+    let mut palette = *palette;
+    for index in TEAM_ENTRIES {
+        let entry = &mut palette[index as usize];
+        let brightness = entry.r.max(entry.g).max(entry.b) as u32;
+        let shade = |channel: u8| (channel as u32 * brightness / 255) as u8;
+        *entry = PaletteEntry {
+            r: shade(color[0]),
+            g: shade(color[1]),
+            b: shade(color[2]),
+        };
+    }
+    palette
+}
+
+/// The palettes of the first team_count teams.
+/// Panics if team_count is above `TEAM_COUNT`.
+pub fn team_palettes(palette: &Palette, team_count: usize) -> Vec<Palette> {
+    (0..team_count)
+        .map(|team| team_palette(palette, team))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remap_team_entries() {
+        let mut palette = [PaletteEntry { r: 1, g: 2, b: 3 }; 256];
+        // the red ramp of the Mac palette
+        for (index, r) in TEAM_ENTRIES.zip([238, 221, 187, 170, 136, 119, 85, 68, 34, 17]) {
+            palette[index as usize] = PaletteEntry { r, g: 0, b: 0 };
+        }
+        assert_eq!(team_palette(&palette, 0), palette);
+        let blue = team_palette(&palette, 1);
+        assert_eq!(blue[214], palette[214]);
+        assert_eq!(
+            blue[215],
+            PaletteEntry {
+                r: 0,
+                g: 89,
+                b: 238
+            }
+        );
+        assert_eq!(blue[224], PaletteEntry { r: 0, g: 6, b: 17 });
+        assert_eq!(blue[225], palette[225]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of the 8 teams")]
+    fn too_many_teams() {
+        team_palettes(&[PaletteEntry::default(); 256], TEAM_COUNT + 1);
+    }
+}
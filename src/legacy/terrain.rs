@@ -1,36 +1,471 @@
-use super::grid::{Coord, Grid2D};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use rand::{rngs::mock::StepRng, Rng};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+use super::{
+    grid::{Coord, Grid, Grid2D},
+    mobility::Mobility,
+    resource::ResourceType,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
 pub enum TerrainType {
-    Grass,
+    Grass = 0,
     Sand,
     Water,
     Resource,
 }
+impl TerrainType {
+    /// The height of the terrain for transitions, resources of unknown type counting as grass
+    fn level(self) -> u8 {
+        match self {
+            TerrainType::Water => 0,
+            TerrainType::Sand => 1,
+            TerrainType::Grass | TerrainType::Resource => 2,
+        }
+    }
+}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct TerrainMap(pub Box<[[u8; 1024]; 1024]>);
+/// The class of a tile, which is what compressed blocks of maps store (e.g. pure water).
+/// Each class corresponds to a range of tiles that are variants of each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TerrainClass(pub u8);
+impl TerrainClass {
+    pub const WATER: Self = Self(0);
+    pub const SAND: Self = Self(15);
+    pub const GRASS: Self = Self(26);
+    /// The first resource class, the one of wheat
+    pub const RESOURCE: Self = Self(31);
+    /// The number of classes having tiles, the last one being the one of algae
+    pub const COUNT: u8 = 35;
+
+    /// Returns the class of a tile whose corners have the given levels of terrain,
+    /// in the order top-left, top-right, bottom-left, bottom-right.
+    /// The levels of the corners must not differ by more than one.
+    ///
+    /// Transitions are indexed by a mask of the corners having the higher terrain,
+    /// top-left being bit 0 and bottom-right bit 3. Between water and sand, the class
+    /// is the mask, so that 0 is water and 15 is sand. Between sand and grass, the
+    /// masks 1 to 14 are the classes 16 to 30, skipping 26 which is grass.
+    fn of_corners(corners: [u8; 4]) -> Self {
+        let low = *corners.iter().min().unwrap();
+        let mask = corners
+            .iter()
+            .enumerate()
+            .filter(|(_, &level)| level > low)
+            .fold(0, |mask, (bit, _)| mask | 1 << bit);
+        match (low, mask) {
+            (2, _) => Self::GRASS,
+            (0, mask) => Self(mask),
+            (_, 0) => Self::SAND,
+            (_, mask) if mask <= 10 => Self(15 + mask),
+            (_, mask) => Self(16 + mask),
+        }
+    }
+    /// Whether this class has tiles, which is not checked when sampling them
+    pub fn is_valid(&self) -> bool {
+        self.0 < Self::COUNT
+    }
+    /// Returns the class of a tile of a given terrain type, surrounded by the same type
+    pub fn pure(ty: TerrainType) -> Self {
+        match ty {
+            TerrainType::Water => Self::WATER,
+            TerrainType::Sand => Self::SAND,
+            TerrainType::Grass => Self::GRASS,
+            TerrainType::Resource => Self::RESOURCE,
+        }
+    }
+    /// Returns the class of a resource, whose 10 variants follow the transitions
+    pub fn resource(ty: ResourceType) -> Self {
+        Self(Self::RESOURCE.0 + u8::from(ty))
+    }
+    /// Returns the type of resource of this class, if any
+    pub fn resource_type(&self) -> Option<ResourceType> {
+        self.0
+            .checked_sub(Self::RESOURCE.0)
+            .and_then(|index| ResourceType::try_from(index).ok())
+    }
+    /// Returns the terrain type of tiles of this class, for maps that only store classes and tiles.
+    /// Only the tiles 0 to 7 are water, the other transitions between water and sand count as sand.
+    pub fn terrain_type(&self) -> TerrainType {
+        match self.0 {
+            0..=1 => TerrainType::Water,
+            2..=25 => TerrainType::Sand,
+            26 => TerrainType::Grass,
+            27..=30 => TerrainType::Sand,
+            _ => TerrainType::Resource,
+        }
+    }
+    /// Returns the class of the range containing this tile
+    pub fn of_tile(tile: u8) -> Self {
+        if tile < 124 {
+            Self(tile / 4)
+        } else {
+            Self(31 + (tile - 124) / 10)
+        }
+    }
+    /// Returns the first tile and the number of tiles of this class
+    pub fn tile_range(&self) -> (u8, u8) {
+        if self.0 < 31 {
+            (self.0 * 4, 4)
+        } else {
+            (4 + (10 * (self.0 - 19)), 10)
+        }
+    }
+    /// Sample an actual tile (e.g. pure water third sprite)
+    pub fn sample_tile(&self, rng: &mut impl Rng) -> u8 {
+        let (first, count) = self.tile_range();
+        first + rng.gen_range(0..count)
+    }
+}
+
+/// Choose the class of a tile from the terrain types of its neighbours.
+///
+/// A corner takes the highest terrain of the four tiles around it, but at most one level above the tile,
+/// so that transitions are drawn on the lower terrain. Hence a feature one tile wide disappears when
+/// surrounded by a higher terrain. Resource tiles keep the first resource class, and are at the level
+/// of its ground as in `TerrainMap::level`.
+pub fn auto_tile(types: &impl Grid2D<TerrainType>, position: Coord) -> TerrainClass {
+    if types.get(position) == TerrainType::Resource {
+        return TerrainClass::RESOURCE;
+    }
+    let resource = TerrainClass::RESOURCE.resource_type().unwrap();
+    auto_tile_levels(types, position, |neighbour| match types.get(neighbour) {
+        TerrainType::Resource => resource.ground().level(),
+        ty => ty.level(),
+    })
+}
+
+/// Choose the class of a non-resource tile from the levels of terrain of its neighbours, see `auto_tile`
+fn auto_tile_levels<T: Copy + PartialEq>(
+    grid: &impl Grid2D<T>,
+    position: Coord,
+    level_at: impl Fn(Coord) -> u8,
+) -> TerrainClass {
+    let level = level_at(position);
+    let corner = |dx: i16, dy: i16| {
+        [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|&(x, y)| position + Coord::new(dx + x, dy + y))
+            .filter(|&neighbour| grid.is_in_bounds(neighbour))
+            .map(&level_at)
+            .max()
+            .unwrap()
+            .min(level + 1)
+    };
+    TerrainClass::of_corners([corner(-1, -1), corner(0, -1), corner(-1, 0), corner(0, 0)])
+}
+
+/// The terrain.
+///
+/// Next to the sprite of each tile, we keep its class if the sprite was sampled from it,
+/// so that the map can be saved again without losing information, and its terrain type.
+/// The tiles whose sprite changed are recorded until taken, for the renderer to update only them.
+#[derive(Clone, Debug)]
+pub struct TerrainMap {
+    /// The sprite index of each tile
+    pub tiles: Grid<u8>,
+    /// The class of each tile whose sprite was sampled, None if the sprite was set explicitly
+    pub classes: Grid<Option<TerrainClass>>,
+    /// The terrain type of each tile, which the transitions drawn on it do not change
+    pub types: Grid<TerrainType>,
+    /// Whether each tile is in changed_tiles
+    changed: Grid<bool>,
+    changed_tiles: Vec<Coord>,
+}
 impl TerrainMap {
+    /// Create a map full of water, using the first variant
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            tiles: Grid::new(width, height, 0),
+            classes: Grid::new(width, height, Some(TerrainClass(0))),
+            types: Grid::new(width, height, TerrainType::Water),
+            changed: Grid::new(width, height, false),
+            changed_tiles: Vec::new(),
+        }
+    }
+    /// Create a map from the terrain type of each tile, choosing transitions with `auto_tile`
+    pub fn from_types(types: &Grid<TerrainType>, rng: &mut impl Rng) -> Self {
+        let mut terrain = Self::new(types.width(), types.height());
+        terrain.types = types.clone();
+        for y in 0..types.height() as i16 {
+            for x in 0..types.width() as i16 {
+                let position = Coord::new(x, y);
+                terrain.sample_class(position, auto_tile(types, position), rng);
+            }
+        }
+        terrain
+    }
+    /// Create a map with explicitly set tiles, whose terrain types are the ones of their classes
+    pub fn from_tiles(tiles: Grid<u8>) -> Self {
+        let mut terrain = Self::new(tiles.width(), tiles.height());
+        for y in 0..tiles.height() as i16 {
+            for x in 0..tiles.width() as i16 {
+                let position = Coord::new(x, y);
+                terrain.set_tile(position, tiles.get(position));
+            }
+        }
+        terrain
+    }
+    pub fn tile(&self, position: Coord) -> u8 {
+        self.tiles.get(position)
+    }
+    /// Set the sprite of a tile explicitly, and its terrain type to the one of its class
+    pub fn set_tile(&mut self, position: Coord, tile: u8) {
+        self.tiles.set(position, tile);
+        self.record_change(position);
+        self.classes.set(position, None);
+        self.types
+            .set(position, TerrainClass::of_tile(tile).terrain_type());
+    }
+    /// Returns the class of a tile, whether its sprite was sampled or not
+    pub fn class(&self, position: Coord) -> TerrainClass {
+        self.sampled_class(position)
+            .unwrap_or_else(|| TerrainClass::of_tile(self.tile(position)))
+    }
+    /// Returns the class of a tile if its sprite was sampled from it
+    pub fn sampled_class(&self, position: Coord) -> Option<TerrainClass> {
+        self.classes.get(position)
+    }
+    /// Set the class of a tile, sampling its sprite, and its terrain type to the one of the class
+    pub fn set_class(&mut self, position: Coord, class: TerrainClass, rng: &mut impl Rng) {
+        self.sample_class(position, class, rng);
+        self.types.set(position, class.terrain_type());
+    }
+    /// Set the class of a tile, sampling its sprite, keeping its terrain type
+    fn sample_class(&mut self, position: Coord, class: TerrainClass, rng: &mut impl Rng) {
+        self.tiles.set(position, class.sample_tile(rng));
+        self.classes.set(position, Some(class));
+        self.record_change(position);
+    }
+    /// Sample again the sprites of all tiles that were sampled from their class
+    pub fn reroll(&mut self, rng: &mut impl Rng) {
+        for y in 0..self.height() as i16 {
+            for x in 0..self.width() as i16 {
+                let position = Coord::new(x, y);
+                if let Some(class) = self.sampled_class(position) {
+                    self.tiles.set(position, class.sample_tile(rng));
+                    self.record_change(position);
+                }
+            }
+        }
+    }
+    /// Record that the sprite of a tile changed, once until the changes are taken
+    fn record_change(&mut self, position: Coord) {
+        if !self.changed.get(position) {
+            self.changed.set(position, true);
+            self.changed_tiles.push(position);
+        }
+    }
+    /// Returns the tiles whose sprite changed since the last call, forgetting them
+    pub fn take_changed_tiles(&mut self) -> Vec<Coord> {
+        for &position in &self.changed_tiles {
+            self.changed.set(position, false);
+        }
+        std::mem::take(&mut self.changed_tiles)
+    }
+    /// Record as changed the tiles whose sprite differs from the ones of a map this one replaces,
+    /// and the changes it recorded
+    pub fn record_changes_from(&mut self, replaced: &TerrainMap) {
+        for &position in &replaced.changed_tiles {
+            if self.is_in_bounds(position) {
+                self.record_change(position);
+            }
+        }
+        for y in 0..self.height() as i16 {
+            for x in 0..self.width() as i16 {
+                let position = Coord::new(x, y);
+                if replaced.is_in_bounds(position) && replaced.tile(position) != self.tile(position)
+                {
+                    self.record_change(position);
+                }
+            }
+        }
+    }
+    /// Set the terrain type of a tile, choosing the transitions of it and its neighbours with `auto_tile`.
+    /// A resource tile keeps its class when set to resource again.
+    pub fn set_type(&mut self, position: Coord, ty: TerrainType, rng: &mut impl Rng) {
+        if ty != TerrainType::Resource || self.get(position) != TerrainType::Resource {
+            self.sample_class(position, TerrainClass::pure(ty), rng);
+            self.types.set(position, ty);
+        }
+        self.retile_around(position, rng);
+    }
+    /// Set a resource on a tile, choosing the transitions of its neighbours with `auto_tile`
+    pub fn set_resource(&mut self, position: Coord, ty: ResourceType, rng: &mut impl Rng) {
+        self.set_class(position, TerrainClass::resource(ty), rng);
+        self.retile_around(position, rng);
+    }
+    /// Returns the type of resource of a tile, if any
+    pub fn resource_type(&self, position: Coord) -> Option<ResourceType> {
+        self.class(position).resource_type()
+    }
+    /// The level of terrain of a tile for transitions, resources being at the level of their ground
+    fn level(&self, position: Coord) -> u8 {
+        match self.resource_type(position) {
+            Some(ty) => ty.ground().level(),
+            None => self.get(position).level(),
+        }
+    }
+    /// Choose again the class of the non-resource tiles around a tile, including itself
+    fn retile_around(&mut self, position: Coord, rng: &mut impl Rng) {
+        // compute all classes before changing any, as they depend on the types of their neighbours
+        let classes: Vec<_> = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| position + Coord::new(dx, dy)))
+            .filter(|&neighbour| self.is_in_bounds(neighbour))
+            .filter(|&neighbour| self.get(neighbour) != TerrainType::Resource)
+            .map(|neighbour| {
+                let class = auto_tile_levels(self, neighbour, |tile| self.level(tile));
+                (neighbour, class)
+            })
+            .collect();
+        for (neighbour, class) in classes {
+            if self.class(neighbour) != class {
+                self.sample_class(neighbour, class, rng);
+            }
+        }
+    }
+    /// Whether a swimming unit can enter a tile, see `passable_for`
     pub fn passable(&self, position: Coord) -> bool {
-        self.get(position) != TerrainType::Resource
+        self.passable_for(position, Mobility::Swimming)
+    }
+    /// Whether a unit of the given mobility can enter a tile
+    pub fn passable_for(&self, position: Coord, mobility: Mobility) -> bool {
+        mobility.can_enter(self.get(position))
+    }
+}
+
+/// Maps are equal when their tiles, classes and types are, whatever changes they recorded
+impl PartialEq for TerrainMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.tiles == other.tiles && self.classes == other.classes && self.types == other.types
     }
 }
+impl Eq for TerrainMap {}
 
 impl Grid2D<TerrainType> for TerrainMap {
-    const W: usize = 1024;
-    const H: usize = 1024;
+    fn width(&self) -> usize {
+        self.tiles.width()
+    }
+    fn height(&self) -> usize {
+        self.tiles.height()
+    }
 
-    fn set(&mut self, _position: Coord, _value: TerrainType) {
-        todo!()
+    /// Set the terrain type using the first variant of each class, see `set_type`.
+    /// `get` returns the set type, even if the tile is drawn as a higher terrain, see `auto_tile`.
+    fn set(&mut self, position: Coord, value: TerrainType) {
+        self.set_type(position, value, &mut StepRng::new(0, 0));
     }
 
     fn get(&self, position: Coord) -> TerrainType {
-        match self.0.get(position) {
-            0..=7 => TerrainType::Water,
-            8..=103 => TerrainType::Sand,
-            104..=107 => TerrainType::Grass,
-            108..=123 => TerrainType::Sand,
-            124.. => TerrainType::Resource,
+        self.types.get(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::legacy::grid::Rect;
+
+    /// Water with a 2 x 2 island of sand at 2, 2, having a tile of grass at 3, 3
+    fn test_types() -> Grid<TerrainType> {
+        let mut types = Grid::new(6, 6, TerrainType::Water);
+        for (x, y) in [(2, 2), (3, 2), (2, 3)] {
+            types.set(Coord::new(x, y), TerrainType::Sand);
+        }
+        types.set(Coord::new(3, 3), TerrainType::Grass);
+        types
+    }
+
+    #[test]
+    fn auto_tile_transitions() {
+        let types = test_types();
+        let class = |x, y| auto_tile(&types, Coord::new(x, y));
+        assert_eq!(class(0, 0), TerrainClass::WATER);
+        // sand at the bottom-right corner
+        assert_eq!(class(1, 1), TerrainClass(8));
+        // sand at the bottom corners
+        assert_eq!(class(2, 1), TerrainClass(12));
+        // the tiles of the island, the grass at the bottom-right corner of the first one
+        assert_eq!(class(2, 2), TerrainClass(15 + 8));
+        assert_eq!(class(3, 3), TerrainClass::GRASS);
+        // sand at the top-left corner
+        assert_eq!(class(4, 4), TerrainClass(1));
+        let terrain = TerrainMap::from_types(&types, &mut StdRng::seed_from_u64(0));
+        for (x, y) in [(1, 1), (2, 2), (3, 2), (3, 3), (4, 4)] {
+            let position = Coord::new(x, y);
+            assert_eq!(terrain.get(position), types.get(position));
+        }
+    }
+
+    #[test]
+    fn terrain_types() {
+        let ty = |class| TerrainClass(class).terrain_type();
+        assert!((0..2).all(|class| ty(class) == TerrainType::Water));
+        assert!((2..26).all(|class| ty(class) == TerrainType::Sand));
+        assert_eq!(ty(26), TerrainType::Grass);
+        assert!((27..31).all(|class| ty(class) == TerrainType::Sand));
+        assert!((31..TerrainClass::COUNT).all(|class| ty(class) == TerrainType::Resource));
+        // explicit tiles of transitions between water and sand
+        let terrain = TerrainMap::from_tiles(Grid::new(1, 1, 7));
+        assert_eq!(terrain.get(Coord::new(0, 0)), TerrainType::Water);
+        let terrain = TerrainMap::from_tiles(Grid::new(1, 1, 8));
+        assert_eq!(terrain.get(Coord::new(0, 0)), TerrainType::Sand);
+    }
+
+    #[test]
+    fn set_pool() {
+        let types = Grid::new(4, 4, TerrainType::Sand);
+        let mut terrain = TerrainMap::from_types(&types, &mut StepRng::new(0, 0));
+        let pool = Rect::new(Coord::new(1, 1), Coord::new(2, 2));
+        terrain.set_rect_value(pool, TerrainType::Water);
+        assert!(terrain.rect_has_value(pool, TerrainType::Water));
+        assert_eq!(terrain.get(Coord::new(0, 0)), TerrainType::Sand);
+    }
+
+    #[test]
+    fn changed_tiles() {
+        let mut terrain = TerrainMap::new(6, 6);
+        assert!(terrain.take_changed_tiles().is_empty());
+        terrain.set(Coord::new(2, 2), TerrainType::Sand);
+        terrain.set(Coord::new(2, 2), TerrainType::Sand);
+        // the tile and the neighbours whose transitions changed, once each
+        let mut changed = terrain.take_changed_tiles();
+        changed.sort_by_key(|position| (position.y, position.x));
+        let around: Vec<_> = (1..=3)
+            .flat_map(|y| (1..=3).map(move |x| Coord::new(x, y)))
+            .collect();
+        assert_eq!(changed, around);
+        assert!(terrain.take_changed_tiles().is_empty());
+        // going back to the initial map changes the same tiles
+        let mut initial = TerrainMap::new(6, 6);
+        initial.record_changes_from(&terrain);
+        let mut changed = initial.take_changed_tiles();
+        changed.sort_by_key(|position| (position.y, position.x));
+        assert_eq!(changed, around);
+    }
+
+    #[test]
+    fn set_type() {
+        let types = test_types();
+        let mut terrain = TerrainMap::new(6, 6);
+        for y in 0..6 {
+            for x in 0..6 {
+                let position = Coord::new(x, y);
+                terrain.set(position, types.get(position));
+            }
         }
+        let rng = &mut StdRng::seed_from_u64(0);
+        let expected = TerrainMap::from_types(&types, rng);
+        assert_eq!(terrain.classes, expected.classes);
+        // remove the island
+        terrain.set_type(Coord::new(3, 3), TerrainType::Water, rng);
+        terrain.set_rect_value(
+            Rect::new(Coord::new(2, 2), Coord::new(2, 2)),
+            TerrainType::Water,
+        );
+        assert_eq!(terrain.classes, TerrainMap::new(6, 6).classes);
     }
 }
@@ -1,123 +1,165 @@
-// see: https://github.com/bevyengine/bevy/discussions/5166
-#![allow(clippy::forget_non_drop)]
-
-use bevy::{
-    math::UVec2,
-    prelude::{Bundle, Commands, Component, Entity, Handle, Image, Query, Res, ResMut, Transform},
-    sprite::{SpriteSheetBundle, TextureAtlas, TextureAtlasSprite},
-};
 use rand::seq::IteratorRandom;
 use rand::Rng;
 
 use super::{
     direction::Direction,
-    grid::{grid_to_world_with_delta, Coord, Grid2D},
+    grid::{Coord, Grid2D},
+    mobility::{Mobility, UnitKind},
     over_map::{OverMap, OverMapTile},
+    pheromone::{GatherMaps, PheromoneChannel, Team},
+    resource::ResourceType,
     terrain::{TerrainMap, TerrainType},
 };
 
-#[derive(Default)]
-pub struct UnitSprites {
-    pub texture_atlas: Handle<TextureAtlas>,
-    pub sprites: Vec<((Handle<Image>, UVec2), usize)>,
-}
+/// Pheromone deposited by a unit descending a map on each tile it reaches, in that map
+pub const TRAIL_EMISSION: u16 = 1024;
+
+/// The team owning a unit or building, whose color it is drawn with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TeamId(pub u8);
+
+/// The identifier of a unit in the simulation, its index in the units
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UnitId(pub u32);
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MoveOrder {
     Idle,
     Walk,
     Swim,
+    Fly,
 }
 
-#[derive(Component)]
-pub struct UnitPosition {
+/// How a unit chooses where to move next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MovePolicy {
+    /// Any direction it can move to
+    #[default]
+    RandomWalk,
+    /// Up the gradient of a pheromone map of its team
+    Climb(PheromoneChannel),
+    /// Down the gradient of a pheromone map of its team, laying a trail on it which others can climb to reach the unit.
+    /// Climbing units do not lay any, as the tile they reach would always be a maximum.
+    Descend(PheromoneChannel),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unit {
     pub position: Coord,
     pub step: u8,
     pub direction: Direction,
     pub order: MoveOrder,
     pub speed: u8,
+    pub kind: UnitKind,
+    pub team: TeamId,
+    pub policy: MovePolicy,
+    /// The resource a worker harvested and brings back to a hive of its team
+    pub carried: Option<ResourceType>,
 }
-
-#[derive(Bundle)]
-pub struct UnitBundle {
-    pub position: UnitPosition,
-    #[bundle]
-    pub sprite: SpriteSheetBundle,
-}
-impl UnitBundle {
-    pub fn try_spawn(
-        position: Coord,
-        unit_sprites: &UnitSprites,
-        over_map: &mut OverMap,
-        commands: &mut Commands,
-    ) -> Option<Entity> {
-        if over_map.get(position) != OverMapTile::Empty {
-            return None;
+impl Unit {
+    /// A new idle unit
+    pub fn new(position: Coord, kind: UnitKind, team: TeamId) -> Self {
+        Self {
+            position,
+            step: 0,
+            direction: Direction::Right,
+            order: MoveOrder::Idle,
+            speed: 3,
+            kind,
+            team,
+            policy: MovePolicy::RandomWalk,
+            carried: None,
         }
-        let id = commands
-            .spawn()
-            .insert_bundle(UnitBundle {
-                position: UnitPosition {
-                    position,
-                    step: 0,
-                    direction: Direction::Right,
-                    order: MoveOrder::Idle,
-                    speed: 3,
-                },
-                sprite: SpriteSheetBundle {
-                    sprite: {
-                        let mut tas = TextureAtlasSprite::new(0);
-                        tas.color = bevy::prelude::Color::RED;
-                        tas
-                    },
-                    texture_atlas: unit_sprites.texture_atlas.clone(),
-                    transform: Transform::from_xyz(0., 0., 1.), //Transform::from_translation(grid_to_world(position)),
-                    ..Default::default()
-                },
-            })
-            .id();
-        over_map.set(position, OverMapTile::Unit(id));
-        Some(id)
+    }
+
+    /// The offset in pixels from its position and the index of the sprite to draw the unit with
+    pub fn animation(&self) -> (Coord, usize) {
+        let dir_index = Into::<u8>::into(self.direction);
+        let moving_delta = (self.direction.delta() * self.step as i16) / 8;
+        let (delta_position, index) = match self.order {
+            MoveOrder::Idle => (Coord::new(0, 0), (self.step >> 2) & !0x7),
+            MoveOrder::Walk => (moving_delta, dir_index << 3 | self.step >> 5),
+            MoveOrder::Swim => (moving_delta, 64 + (dir_index << 3 | self.step >> 5)),
+            // assuming the last 64 sprites are the flying explorer
+            MoveOrder::Fly => (moving_delta, 128 + (dir_index << 3 | self.step >> 5)),
+        };
+        (delta_position, index as usize)
     }
 }
 
+/// The directions in which a unit of the given mobility can move
 pub fn valid_directions<'a>(
-    unit: &'a UnitPosition,
+    unit: &'a Unit,
+    mobility: Mobility,
     terrain: &'a TerrainMap,
     over_map: &'a OverMap,
 ) -> impl Iterator<Item = Direction> + 'a {
-    Direction::all().filter(|dir| {
+    Direction::all().filter(move |dir| {
         let position = unit.position + dir.delta();
-        TerrainMap::is_in_bounds(position)
-            && terrain.passable(position)
+        terrain.is_in_bounds(position)
+            && terrain.passable_for(position, mobility)
             && over_map.get(position) == OverMapTile::Empty
     })
 }
 
+/// The direction a unit moves to following its policy, None if it cannot move.
+///
+/// When following a pheromone map, the unit goes to the neighbour improving the most on its own tile.
+/// On flat areas and at extrema of the map, it falls back to a random walk.
+pub fn choose_direction(
+    unit: &Unit,
+    mobility: Mobility,
+    terrain: &TerrainMap,
+    over_map: &OverMap,
+    team: &Team,
+    gather_maps: &GatherMaps,
+    rng: &mut impl Rng,
+) -> Option<Direction> {
+    let directions = valid_directions(unit, mobility, terrain, over_map);
+    let (channel, sign) = match unit.policy {
+        MovePolicy::RandomWalk => return directions.choose(rng),
+        MovePolicy::Climb(channel) => (channel, 1),
+        MovePolicy::Descend(channel) => (channel, -1),
+    };
+    let score = |dir: Option<Direction>| {
+        let position = unit.position + dir.map_or(Coord::new(0, 0), |dir| dir.delta());
+        sign * team.intensity(gather_maps, channel, position) as i64
+    };
+    let directions: Vec<_> = directions.collect();
+    let best = directions.iter().map(|&dir| score(Some(dir))).max();
+    match best {
+        Some(best) if best > score(None) => directions
+            .into_iter()
+            .filter(|&dir| score(Some(dir)) == best)
+            .choose(rng),
+        _ => directions.into_iter().choose(rng),
+    }
+}
+
 pub fn next_order(
-    e: Entity,
-    unit: &mut UnitPosition,
+    id: UnitId,
+    unit: &mut Unit,
     terrain: &TerrainMap,
     over_map: &mut OverMap,
+    team: &Team,
+    gather_maps: &GatherMaps,
     rng: &mut impl Rng,
 ) {
     // find next position and change animation given terrain
-    let dir = valid_directions(unit, terrain, over_map).choose(rng);
+    let mobility = unit.kind.mobility();
+    let dir = choose_direction(unit, mobility, terrain, over_map, team, gather_maps, rng);
     match dir {
         Some(dir) => {
             unit.direction = dir;
             let next_position = unit.position + dir.delta();
-            over_map.set(next_position, OverMapTile::Unit(e));
-            unit.order = match terrain.get(next_position) {
-                TerrainType::Water => MoveOrder::Swim,
-                TerrainType::Grass | TerrainType::Sand => {
-                    if terrain.get(unit.position) == TerrainType::Water {
-                        MoveOrder::Swim
-                    } else {
-                        MoveOrder::Walk
-                    }
-                }
-                _ => panic!("The next position is not a passable location"),
+            over_map.set(next_position, OverMapTile::Unit(id));
+            let in_water = |position| terrain.get(position) == TerrainType::Water;
+            unit.order = if mobility == Mobility::Flying {
+                MoveOrder::Fly
+            } else if in_water(next_position) || in_water(unit.position) {
+                MoveOrder::Swim
+            } else {
+                MoveOrder::Walk
             };
         }
         None => {
@@ -128,54 +170,102 @@ pub fn next_order(
         MoveOrder::Idle => 3,
         MoveOrder::Walk => 10,
         MoveOrder::Swim => 5,
+        MoveOrder::Fly => 12,
     };
 }
 
-pub fn move_units(
-    unit_sprites: Res<UnitSprites>,
-    terrain: Res<TerrainMap>,
-    mut over_map: ResMut<OverMap>,
-    mut query: Query<(
-        Entity,
-        &mut UnitPosition,
-        &mut TextureAtlasSprite,
-        &mut Transform,
-    )>,
-) {
-    let mut rng = rand::thread_rng();
-    for (e, mut unit, mut sprite, mut transform) in query.iter_mut() {
-        // do movement
-        let movement_ended = unit.step as u32 + unit.speed as u32 > 255;
-        if movement_ended {
-            match unit.order {
-                MoveOrder::Idle => {}
-                MoveOrder::Walk | MoveOrder::Swim => {
-                    over_map.set(unit.position, OverMapTile::Empty);
-                    let delta = unit.direction.delta();
-                    unit.position += delta;
-                    debug_assert_eq!(over_map.get(unit.position), OverMapTile::Unit(e));
+/// Advance the movement of a unit by one tick, depositing the pheromone of its policy on the tile it reaches,
+/// and choosing its next order when the current one ends.
+/// Returns whether the order ended.
+pub fn move_unit(
+    id: UnitId,
+    unit: &mut Unit,
+    terrain: &TerrainMap,
+    over_map: &mut OverMap,
+    team: &mut Team,
+    gather_maps: &GatherMaps,
+    rng: &mut impl Rng,
+) -> bool {
+    let movement_ended = unit.step as u32 + unit.speed as u32 > 255;
+    if movement_ended {
+        match unit.order {
+            MoveOrder::Idle => {}
+            MoveOrder::Walk | MoveOrder::Swim | MoveOrder::Fly => {
+                over_map.set(unit.position, OverMapTile::Empty);
+                let delta = unit.direction.delta();
+                unit.position += delta;
+                debug_assert_eq!(over_map.get(unit.position), OverMapTile::Unit(id));
+                if let MovePolicy::Descend(channel) = unit.policy {
+                    team.pheromone_map_mut(channel)
+                        .deposit(unit.position, TRAIL_EMISSION);
                 }
             }
         }
-        unit.step = unit.step.wrapping_add(unit.speed);
-        // update display
-        let dir_index = Into::<u8>::into(unit.direction);
-        let (delta_position, index) = match unit.order {
-            MoveOrder::Idle => (Coord::new(0, 0), (unit.step >> 2) & !0x7),
-            MoveOrder::Walk => (
-                (unit.direction.delta() * unit.step as i16) / 8,
-                dir_index << 3 | unit.step >> 5,
-            ),
-            MoveOrder::Swim => (
-                (unit.direction.delta() * unit.step as i16) / 8,
-                64 + (dir_index << 3 | unit.step >> 5),
-            ),
+    }
+    unit.step = unit.step.wrapping_add(unit.speed);
+    if movement_ended {
+        next_order(id, unit, terrain, over_map, team, gather_maps, rng);
+    }
+    movement_ended
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::legacy::pheromone::{PheromoneKind, PheromoneMap};
+
+    #[test]
+    fn follow_gradient() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut terrain = TerrainMap::new(8, 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                terrain.set(Coord::new(x, y), TerrainType::Grass);
+            }
+        }
+        let over_map = OverMap::new(8, 8);
+        let channel = PheromoneChannel {
+            resource: ResourceType::Wood,
+            kind: PheromoneKind::Gather,
         };
-        transform.translation = grid_to_world_with_delta(unit.position, delta_position);
-        sprite.index = unit_sprites.sprites[index as usize].1;
-        // next movement
-        if movement_ended {
-            next_order(e, &mut unit, &terrain, &mut over_map, &mut rng);
+        let mut team = Team::new(8, 8);
+        let gather_maps = [(); 4].map(|_| PheromoneMap::new(8, 8));
+        let mut unit = Unit::new(Coord::new(4, 4), UnitKind::Warrior, TeamId(0));
+        let mut choose = |unit: &Unit, team: &Team| {
+            let terrain = &terrain;
+            choose_direction(
+                unit,
+                Mobility::Land,
+                terrain,
+                &over_map,
+                team,
+                &gather_maps,
+                rng,
+            )
+            .unwrap()
+        };
+
+        // on a flat map, any direction
+        unit.policy = MovePolicy::Climb(channel);
+        let directions: Vec<_> = (0..64).map(|_| choose(&unit, &team)).collect();
+        assert!(Direction::all().all(|dir| directions.contains(&dir)));
+
+        // intensity increasing toward the bottom right
+        let map = team.pheromone_map_mut(channel);
+        map.for_each(|_, position| (position.x + position.y) as u16 * 10);
+        for _ in 0..16 {
+            assert_eq!(choose(&unit, &team), Direction::BottomRight);
+        }
+        unit.policy = MovePolicy::Descend(channel);
+        for _ in 0..16 {
+            assert_eq!(choose(&unit, &team), Direction::TopLeft);
         }
+        // at the minimum, random walk
+        unit.position = Coord::new(0, 0);
+        let directions: Vec<_> = (0..64).map(|_| choose(&unit, &team)).collect();
+        assert!(directions.contains(&Direction::Right));
+        assert!(directions.contains(&Direction::BottomRight));
     }
 }
@@ -1,4 +1,5 @@
 pub mod legacy;
 
+#[cfg(feature = "embedded-assets")]
 #[macro_use]
 extern crate lazy_static;